@@ -1,39 +1,301 @@
 use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
-use log::info;
+use distributed_systems::intervals::IntervalSet;
+use distributed_systems::random::{fnv1a, jittered, mix};
+use distributed_systems::topology::Topology;
+use distributed_systems::{config, node, rpc};
+use log::{info, warn};
 use maelstrom::protocol::Message;
 use maelstrom::{done, Node, Result, Runtime};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_context::context::{Context, Handle};
 
 #[derive(Clone, Default)]
 struct Handler {
     state: Arc<Mutex<NodeState>>,
+    config: Config,
+    // cancels the running gossip loop when dropped
+    gossip_loop: Arc<Mutex<Option<Handle>>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Mode {
+    /// New values only leave the node on the next gossip round.
+    #[default]
+    Periodic,
+    /// New values are forwarded to neighbors as soon as they arrive; gossip
+    /// rounds only repair what got lost, e.g. during a partition.
+    Eager,
+    /// Plumtree: new values are pushed along a spanning tree that nodes
+    /// prune out of the neighbor graph as they see duplicates. The other
+    /// edges only carry ids (`ihave`) on gossip rounds, and get grafted back
+    /// into the tree when a value didn't arrive over it in time.
+    Plumtree,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "periodic" => Ok(Mode::Periodic),
+            "eager" => Ok(Mode::Eager),
+            "plumtree" => Ok(Mode::Plumtree),
+            _ => Err(format!("unknown broadcast mode: {s}")),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    mode: Mode,
+    topology: Topology,
+    gossip_interval: Duration,
+    // random extra delay added to every gossip round, so nodes don't tick in lockstep
+    gossip_jitter: Duration,
+    // max values per gossip message, 0 for no limit
+    max_batch: usize,
+    // when set, new broadcasts are pushed to neighbors once this window
+    // closes instead of waiting for the next gossip round
+    batch_window: Option<Duration>,
+    // how long a node waits for a value it learned about through `ihave`
+    // before grafting the announcing peer
+    graft_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Periodic,
+            topology: Topology::Given,
+            gossip_interval: Duration::from_millis(300),
+            gossip_jitter: Duration::ZERO,
+            max_batch: 0,
+            batch_window: None,
+            graft_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+impl Config {
+    fn load() -> Self {
+        let batch_window = config::millis_or("batch-window-ms", "BROADCAST_BATCH_WINDOW_MS", 0);
+        Self {
+            mode: config::parse_or("mode", "BROADCAST_MODE", Mode::Periodic),
+            topology: config::parse_or("topology", "BROADCAST_TOPOLOGY", Topology::Given),
+            gossip_interval: config::millis_or(
+                "gossip-interval-ms",
+                "BROADCAST_GOSSIP_INTERVAL_MS",
+                300,
+            ),
+            gossip_jitter: config::millis_or("gossip-jitter-ms", "BROADCAST_GOSSIP_JITTER_MS", 0),
+            max_batch: config::parse_or("max-batch", "BROADCAST_MAX_BATCH", 0),
+            batch_window: (!batch_window.is_zero()).then_some(batch_window),
+            graft_timeout: config::millis_or("graft-timeout-ms", "BROADCAST_GRAFT_TIMEOUT_MS", 100),
+        }
+    }
+
+    fn next_gossip_in(&self) -> Duration {
+        jittered(self.gossip_interval, self.gossip_jitter)
+    }
 }
 
 #[derive(Clone, Default)]
 struct NodeState {
     messages: HashSet<u64>,
+    // `messages` in insertion order, positions in it are the read cursors
+    log: Vec<u64>,
+    // JSON payloads by id, for values that aren't just their own id
+    payloads: HashMap<u64, Value>,
+    // order independent hash of `messages`, so peers can compare sets cheaply
+    digest: u64,
     neighbors: Vec<String>,
+    // neighbors pruned from the broadcast tree, only used in plumtree mode
+    lazy: HashSet<String>,
+    peers: HashMap<String, Peer>,
+    broadcasts: u64,
+    gossip_sent: u64,
+    // local broadcasts some neighbor doesn't have yet, and since when
+    spreading: HashMap<u64, Instant>,
+    spread: Spread,
+    flush_scheduled: bool,
+}
+
+/// How long local broadcasts took until every neighbor had them.
+#[derive(Clone, Copy, Default)]
+struct Spread {
+    count: u32,
+    total: Duration,
+    max: Duration,
+}
+
+impl Spread {
+    fn mean(&self) -> Duration {
+        self.total / self.count.max(1)
+    }
+}
+
+/// What we know about a neighbor's copy of the set.
+#[derive(Clone, Default)]
+struct Peer {
+    // values the peer is known to have, either because it sent them to us
+    // or acknowledged them
+    known: HashSet<u64>,
+    lagging: bool,
+}
+
+impl NodeState {
+    fn add(&mut self, msg: u64, payload: Option<Value>) -> bool {
+        if !self.messages.insert(msg) {
+            return false;
+        }
+        self.digest ^= mix(msg);
+        self.log.push(msg);
+        if let Some(payload) = payload {
+            self.payloads.insert(msg, payload);
+        }
+        true
+    }
+
+    fn value(&self, msg: u64) -> Value {
+        self.payloads
+            .get(&msg)
+            .cloned()
+            .unwrap_or_else(|| Value::from(msg))
+    }
+
+    /// Values added after the first `since`, at most `limit` of them, and the
+    /// cursor to continue from.
+    fn read_since(&self, since: usize, limit: Option<usize>) -> (Vec<Value>, usize) {
+        let start = since.min(self.log.len());
+        let end = match limit {
+            Some(limit) => start.saturating_add(limit).min(self.log.len()),
+            None => self.log.len(),
+        };
+        let values = self.log[start..end].iter().map(|m| self.value(*m));
+        (values.collect(), end)
+    }
+
+    /// Settles the local broadcasts every neighbor now knows about.
+    fn track_spread(&mut self) {
+        let now = Instant::now();
+        let NodeState {
+            spreading,
+            spread,
+            neighbors,
+            peers,
+            ..
+        } = self;
+        spreading.retain(|msg, since| {
+            let everywhere = neighbors
+                .iter()
+                .all(|n| peers.get(n).is_some_and(|p| p.known.contains(msg)));
+            if everywhere {
+                let took = now - *since;
+                spread.count += 1;
+                spread.total += took;
+                spread.max = spread.max.max(took);
+            }
+            !everywhere
+        });
+    }
+
+    /// Handles a peer's `gossip_ok` for `delta`, carrying its set summary.
+    fn on_ack(&mut self, node: &str, delta: HashSet<u64>, count: usize, digest: u64) {
+        let (ours, same) = (self.messages.len(), digest == self.digest);
+        let all = (same && count == ours).then(|| self.messages.clone());
+        let peer = self.peers.entry(node.to_string()).or_default();
+        match all {
+            // identical sets, nothing left to send until we learn something new
+            Some(all) => peer.known = all,
+            None => {
+                peer.known.extend(delta);
+                if count < peer.known.len() {
+                    // the peer has less than we thought, e.g. it restarted
+                    warn!(
+                        "{node} reports {count} values, expected at least {}, resending",
+                        peer.known.len()
+                    );
+                    peer.known.clear();
+                }
+            }
+        }
+
+        let lagging = count < ours;
+        if lagging != peer.lagging {
+            peer.lagging = lagging;
+            if lagging {
+                info!("{node} is lagging: it has {count} values, we have {ours}");
+            } else {
+                info!("{node} caught up with {count} values");
+            }
+        }
+        self.track_spread();
+    }
+}
+
+/// Dedup key of a broadcast value. Plain integers are their own id, anything
+/// else is deduplicated by a hash of its JSON encoding. That is the same on
+/// every node, since objects serialize with sorted keys. A client `id` is
+/// hashed too, behind a prefix no JSON encoding starts with, so it can't
+/// collide with either.
+fn message_id(message: &Value, id: Option<u64>) -> u64 {
+    match (id, message.as_u64()) {
+        (Some(id), _) => fnv1a(format!("id:{id}").as_bytes()),
+        (None, Some(plain)) => plain,
+        (None, None) => fnv1a(message.to_string().as_bytes()),
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "snake_case", tag = "type")]
 enum Request {
     Init {},
-    Read {},
+    Read {
+        // only values added after this many, as returned in `cursor`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
     Gossip {
-        seen: HashSet<u64>,
+        seen: IntervalSet,
+        // `[id, payload]` pairs for the values in `seen` that have one; not a
+        // map, as integer keys don't survive the tagged enum
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        payloads: Vec<(u64, Value)>,
+    },
+    GossipOk {
+        count: usize,
+        digest: u64,
     },
     BroadcastOk {},
     ReadOk {
-        messages: HashSet<u64>,
+        messages: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cursor: Option<usize>,
     },
     Broadcast {
-        message: u64,
+        message: Value,
+        // dedup key chosen by the client, see `message_id`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+    },
+    Ihave {
+        ids: IntervalSet,
+    },
+    IhaveOk {
+        missing: IntervalSet,
+    },
+    Graft {
+        ids: IntervalSet,
     },
+    Prune {},
     Topology {
         topology: HashMap<String, Vec<String>>,
     },
@@ -43,41 +305,139 @@ enum Request {
 impl Node for Handler {
     async fn process(&self, runtime: Runtime, request: Message) -> Result<()> {
         let msg: Result<Request> = request.body.as_obj();
-        let rt = runtime.clone();
-        let s = self.clone();
         match msg {
-            Ok(Request::Broadcast { message: element }) => {
-                self.try_add_msg(element);
+            Ok(Request::Broadcast { message, id }) => {
+                self.state.lock().unwrap().broadcasts += 1;
+                let element = message_id(&message, id);
+                if self.try_add_msg(element, message) {
+                    {
+                        let mut s = self.state.lock().unwrap();
+                        s.spreading.insert(element, Instant::now());
+                        s.track_spread();
+                    }
+                    match self.config.mode {
+                        Mode::Eager | Mode::Plumtree => {
+                            self.push(&runtime, HashSet::from([element]), None)
+                        }
+                        Mode::Periodic => self.schedule_flush(&runtime),
+                    }
+                }
                 runtime.reply_ok(request).await
             }
-            Ok(Request::Gossip { seen }) => {
-                self.state.lock().unwrap().messages.extend(seen);
+            Ok(Request::Gossip { seen, payloads }) => {
+                let mut payloads: HashMap<u64, Value> = payloads.into_iter().collect();
+                let plumtree = self.config.mode == Mode::Plumtree;
+                let (fresh, count, digest, prune) = {
+                    let mut s = self.state.lock().unwrap();
+                    let fresh: HashSet<u64> = seen
+                        .iter()
+                        .filter(|v| s.add(*v, payloads.remove(v)))
+                        .collect();
+                    let peer = s.peers.entry(request.src.clone()).or_default();
+                    peer.known.extend(seen.iter());
+                    s.track_spread();
+                    // nothing new: the values already reached us over a
+                    // shorter path, so this edge can leave the tree
+                    let prune = plumtree
+                        && fresh.is_empty()
+                        && !seen.is_empty()
+                        && s.lazy.insert(request.src.clone());
+                    if !fresh.is_empty() {
+                        s.lazy.remove(&request.src);
+                    }
+                    (fresh, s.messages.len(), s.digest, prune)
+                };
+                if prune {
+                    info!("pruning {} from the broadcast tree", request.src);
+                    runtime.send(&request.src, Request::Prune {}).await?;
+                }
+                if self.config.mode != Mode::Periodic && !fresh.is_empty() {
+                    self.push(&runtime, fresh, Some(&request.src));
+                }
+                // eager pushes are one-way, only gossip rounds are acknowledged
+                if request.body.msg_id == 0 {
+                    return Ok(());
+                }
+                runtime
+                    .reply(request, Request::GossipOk { count, digest })
+                    .await
+            }
+            Ok(Request::Ihave { ids }) => {
+                let missing: IntervalSet = {
+                    let mut s = self.state.lock().unwrap();
+                    let missing = ids.iter().filter(|v| !s.messages.contains(v)).collect();
+                    // no need to announce these back
+                    let peer = s.peers.entry(request.src.clone()).or_default();
+                    peer.known.extend(ids.iter());
+                    s.track_spread();
+                    missing
+                };
+                if !missing.is_empty() {
+                    self.schedule_graft(&runtime, request.src.clone(), missing.clone());
+                }
+                runtime.reply(request, Request::IhaveOk { missing }).await
+            }
+            Ok(Request::Graft { ids }) => {
+                let values: HashSet<u64> = {
+                    let mut s = self.state.lock().unwrap();
+                    s.lazy.remove(&request.src);
+                    ids.iter().filter(|v| s.messages.contains(v)).collect()
+                };
+                info!(
+                    "{} grafted itself back onto the broadcast tree",
+                    request.src
+                );
+                if !values.is_empty() {
+                    self.send_gossip(&runtime, request.src, values);
+                    self.log_metrics(1);
+                }
                 Ok(())
             }
-            Ok(Request::Read {}) => {
-                let msgs = self.get_messages();
-                let res = Request::ReadOk { messages: msgs };
-                runtime.reply(request, res).await
+            Ok(Request::Prune {}) => {
+                self.state.lock().unwrap().lazy.insert(request.src);
+                Ok(())
             }
-            Ok(Request::Topology { topology }) => {
-                let neighbours = topology.get(runtime.node_id()).unwrap();
-                self.state.lock().unwrap().neighbors = neighbours.clone();
-                info!("My neighbors are {:?}", neighbours);
-                tokio::spawn(async move {
-                    // generate gossip events
-                    loop {
-                        tokio::time::sleep(Duration::from_millis(300)).await;
-                        let n = s.state.lock().unwrap().neighbors.clone();
-                        for node in n {
-                            rt.call_async(
-                                node,
-                                Request::Gossip {
-                                    seen: s.get_messages(),
-                                },
-                            )
+            Ok(Request::Read { since, limit }) => {
+                let res = match (since, limit) {
+                    (None, None) => Request::ReadOk {
+                        messages: self.get_messages(),
+                        cursor: None,
+                    },
+                    _ => {
+                        let s = self.state.lock().unwrap();
+                        let (messages, cursor) = s.read_since(since.unwrap_or(0), limit);
+                        Request::ReadOk {
+                            messages,
+                            cursor: Some(cursor),
                         }
                     }
-                });
+                };
+                runtime.reply(request, res).await
+            }
+            Ok(Request::Topology { topology }) => {
+                let neighbours = match self
+                    .config
+                    .topology
+                    .neighbors(runtime.node_id(), runtime.nodes())
+                {
+                    Some(generated) => generated,
+                    None => topology.get(runtime.node_id()).cloned().unwrap_or_default(),
+                };
+                {
+                    let mut s = self.state.lock().unwrap();
+                    s.neighbors = neighbours.clone();
+                    // every edge starts out in the broadcast tree
+                    s.lazy.clear();
+                }
+                info!(
+                    "My neighbors are {:?} ({} topology)",
+                    neighbours, self.config.topology
+                );
+                if neighbours.is_empty() {
+                    self.stop_gossip();
+                } else {
+                    self.start_gossip(&runtime);
+                }
                 runtime.reply_ok(request).await
             }
             _ => done(runtime, request),
@@ -86,31 +446,237 @@ impl Node for Handler {
 }
 
 impl Handler {
-    fn try_add_msg(&self, msg: u64) -> bool {
-        let mut s = self.state.lock().unwrap();
-        if !s.messages.contains(&msg) {
-            s.messages.insert(msg);
-            return true;
+    /// (Re)starts the gossip loop. There is only ever one: a previous loop
+    /// is cancelled first, so repeated `topology` messages don't multiply
+    /// gossip traffic.
+    fn start_gossip(&self, runtime: &Runtime) {
+        let (mut ctx, handle) = Context::new();
+        if let Some(previous) = self.gossip_loop.lock().unwrap().replace(handle) {
+            previous.cancel();
+        }
+        let (rt, s) = (runtime.clone(), self.clone());
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = ctx.done() => break,
+                    _ = tokio::time::sleep(s.config.next_gossip_in()) => s.gossip(&rt),
+                }
+            }
+            info!("gossip loop stopped");
+        });
+    }
+
+    /// Stops the gossip loop, if any. In-flight gossip messages still finish.
+    fn stop_gossip(&self) {
+        if let Some(handle) = self.gossip_loop.lock().unwrap().take() {
+            handle.cancel();
+        }
+    }
+
+    fn try_add_msg(&self, msg: u64, message: Value) -> bool {
+        let payload = (message != msg).then_some(message);
+        self.state.lock().unwrap().add(msg, payload)
+    }
+
+    /// Sends every neighbor the values it is not known to have yet. Pruned
+    /// neighbors only get their ids, and so does everyone in plumtree mode:
+    /// a repeated value on a tree edge would read as a duplicate and prune it.
+    fn gossip(&self, runtime: &Runtime) {
+        let (n, lazy) = {
+            let s = self.state.lock().unwrap();
+            (s.neighbors.clone(), s.lazy.clone())
+        };
+        let mut sent = 0;
+        for node in n {
+            let delta = self.unknown_to(&node);
+            if delta.is_empty() {
+                continue;
+            }
+            sent += 1;
+            if self.config.mode == Mode::Plumtree || lazy.contains(&node) {
+                self.send_ihave(runtime, node, delta);
+            } else {
+                self.send_gossip(runtime, node, delta);
+            }
+        }
+        if sent > 0 {
+            self.log_metrics(sent);
+        }
+    }
+
+    /// Forwards freshly learned `values` to every neighbor in the broadcast
+    /// tree but `except`. Pushes are not acknowledged: a neighbor only counts
+    /// as knowing them once it acks a gossip round, which also repairs lost
+    /// pushes.
+    fn push(&self, runtime: &Runtime, values: HashSet<u64>, except: Option<&str>) {
+        let (n, lazy) = {
+            let s = self.state.lock().unwrap();
+            (s.neighbors.clone(), s.lazy.clone())
+        };
+        let mut sent = 0;
+        for node in n {
+            if Some(node.as_str()) == except || lazy.contains(&node) {
+                continue;
+            }
+            sent += 1;
+            let msg = Request::Gossip {
+                seen: values.iter().copied().collect(),
+                payloads: self.payloads(&values),
+            };
+            let rt = runtime.clone();
+            tokio::spawn(async move {
+                if let Err(err) = rt.send(&node, msg).await {
+                    warn!("push to {node} failed: {err}");
+                }
+            });
+        }
+        if sent > 0 {
+            self.log_metrics(sent);
         }
-        false
     }
 
-    fn get_messages(&self) -> HashSet<u64> {
-        self.state
-            .lock()
-            .unwrap()
-            .messages
-            .iter()
-            .copied()
+    /// `[id, payload]` pairs for the values in `ids` that have one.
+    fn payloads(&self, ids: &HashSet<u64>) -> Vec<(u64, Value)> {
+        let s = self.state.lock().unwrap();
+        ids.iter()
+            .filter_map(|m| Some((*m, s.payloads.get(m)?.clone())))
             .collect()
     }
-}
 
-fn main() -> Result<()> {
-    Runtime::init(try_main())
+    fn send_gossip(&self, runtime: &Runtime, node: String, delta: HashSet<u64>) {
+        let payloads = self.payloads(&delta);
+        let (rt, s) = (runtime.clone(), self.clone());
+        tokio::spawn(async move {
+            let ack: Result<Request> = rpc::call_with_timeout(
+                &rt,
+                s.config.gossip_interval,
+                node.clone(),
+                Request::Gossip {
+                    seen: delta.iter().copied().collect(),
+                    payloads,
+                },
+            )
+            .await;
+            if let Ok(Request::GossipOk { count, digest }) = ack {
+                s.state.lock().unwrap().on_ack(&node, delta, count, digest);
+            }
+        });
+    }
+
+    /// Announces `ids` to a pruned neighbor. Whatever it reports missing is
+    /// announced again next round, until it grafts us and fetches them.
+    fn send_ihave(&self, runtime: &Runtime, node: String, ids: HashSet<u64>) {
+        let (rt, s) = (runtime.clone(), self.clone());
+        tokio::spawn(async move {
+            let ack: Result<Request> = rpc::call_with_timeout(
+                &rt,
+                s.config.gossip_interval,
+                node.clone(),
+                Request::Ihave {
+                    ids: ids.iter().copied().collect(),
+                },
+            )
+            .await;
+            if let Ok(Request::IhaveOk { missing }) = ack {
+                let mut state = s.state.lock().unwrap();
+                let peer = state.peers.entry(node).or_default();
+                peer.known
+                    .extend(ids.into_iter().filter(|v| !missing.contains(*v)));
+            }
+        });
+    }
+
+    /// Grafts `node` back into the broadcast tree if `missing` still hasn't
+    /// arrived over it once the graft timeout expires.
+    fn schedule_graft(&self, runtime: &Runtime, node: String, missing: IntervalSet) {
+        let (rt, s) = (runtime.clone(), self.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(s.config.graft_timeout).await;
+            let ids: IntervalSet = {
+                let mut state = s.state.lock().unwrap();
+                let ids: IntervalSet = missing
+                    .iter()
+                    .filter(|v| !state.messages.contains(v))
+                    .collect();
+                if !ids.is_empty() {
+                    state.lazy.remove(&node);
+                }
+                ids
+            };
+            if ids.is_empty() {
+                return;
+            }
+            info!("grafting {node} for {} missing values", ids.len());
+            if let Err(err) = rt.send(&node, Request::Graft { ids }).await {
+                warn!("graft to {node} failed: {err}");
+            }
+        });
+    }
+
+    /// In batching mode, pushes everything that arrived within the window in
+    /// one go once it closes.
+    fn schedule_flush(&self, runtime: &Runtime) {
+        let Some(window) = self.config.batch_window else {
+            return;
+        };
+        {
+            let mut s = self.state.lock().unwrap();
+            if s.flush_scheduled {
+                return;
+            }
+            s.flush_scheduled = true;
+        }
+        let (rt, s) = (runtime.clone(), self.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            s.state.lock().unwrap().flush_scheduled = false;
+            s.gossip(&rt);
+        });
+    }
+
+    /// Values we have that `node` is not known to have yet, at most
+    /// `max_batch` of them.
+    fn unknown_to(&self, node: &str) -> HashSet<u64> {
+        let s = self.state.lock().unwrap();
+        let limit = match self.config.max_batch {
+            0 => usize::MAX,
+            n => n,
+        };
+        match s.peers.get(node) {
+            Some(peer) => s
+                .messages
+                .difference(&peer.known)
+                .take(limit)
+                .copied()
+                .collect(),
+            None => s.messages.iter().take(limit).copied().collect(),
+        }
+    }
+
+    fn log_metrics(&self, sent: u64) {
+        let mut s = self.state.lock().unwrap();
+        s.gossip_sent += sent;
+        info!(
+            "sent {} gossip msgs for {} local broadcasts ({:.2} msgs/op), \
+             {} reached every neighbor after {:?} on average, {:?} at most",
+            s.gossip_sent,
+            s.broadcasts,
+            s.gossip_sent as f64 / s.broadcasts.max(1) as f64,
+            s.spread.count,
+            s.spread.mean(),
+            s.spread.max
+        );
+    }
+
+    fn get_messages(&self) -> Vec<Value> {
+        let s = self.state.lock().unwrap();
+        s.log.iter().map(|m| s.value(*m)).collect()
+    }
 }
 
-async fn try_main() -> Result<()> {
-    let handler = Arc::new(Handler::default());
-    Runtime::new().with_handler(handler).run().await
+fn main() -> Result<()> {
+    node::run(|_| Handler {
+        config: Config::load(),
+        ..Handler::default()
+    })
 }
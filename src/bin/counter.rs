@@ -1,51 +1,415 @@
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
-use log::info;
+use distributed_systems::crdt::PnCounter;
+use distributed_systems::protocol::Init;
+use distributed_systems::{config, node, random, rpc};
+use log::{info, warn};
 use maelstrom::kv::{seq_kv, Storage, KV};
 use maelstrom::protocol::Message;
-use maelstrom::{done, Node, Result, Runtime};
+use maelstrom::{done, Error, Node, Result, Runtime};
 use serde::{Deserialize, Serialize};
+use tokio_context::context::Context;
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "type")]
 enum Request {
-    Add {
-        delta: u64,
-    },
+    Add { delta: i64 },
     Read {},
     ReadBucket {},
-    Init {
-        node_ids: Vec<String>,
-        node_id: String,
-    },
+    Merge { counter: PnCounter },
+    Init(Init),
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "type")]
+#[allow(clippy::enum_variant_names)]
 enum Response {
     AddOk {},
     InitOk {},
-    ReadOk { value: u64 },
-    ReadBucketOk { value: u64 },
+    ReadOk { value: i64 },
+    ReadBucketOk { value: i64 },
+    MergeOk { counter: PnCounter },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Mode {
+    /// Every node owns a bucket in seq-kv, reads sum them up.
+    #[default]
+    Buckets,
+    /// Every node keeps a PN-counter and gossips it to the others, no KV
+    /// involved.
+    Crdt,
+    /// A single counter key in seq-kv that every node CASes. Reads write a
+    /// unique value first, so seq-kv has to serve them a state at least as
+    /// new as that write.
+    Shared,
+}
+
+// the counter key in shared mode
+const SHARED_KEY: &str = "counter";
+
+// the write-behind bucket waits a flush interval for seq-kv at first, twice
+// as long after every failed load or flush, but never longer than this
+const MAX_KV_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "buckets" => Ok(Mode::Buckets),
+            "crdt" => Ok(Mode::Crdt),
+            "shared" => Ok(Mode::Shared),
+            _ => Err(format!("unknown counter mode: {s}")),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    mode: Mode,
+    gossip_interval: Duration,
+    // conflicting CAS writes to retry before failing an add
+    cas_retries: u32,
+    // wait before the first retry, doubled on every further one up to
+    // `cas_max_backoff`
+    cas_backoff: Duration,
+    cas_max_backoff: Duration,
+    // how long a read waits for each bucket before using the cached value
+    read_timeout: Duration,
+    // when set, adds only update this node's bucket in memory, and it is
+    // written to seq-kv on this interval. Adds are acknowledged before they
+    // are flushed, so a node that crashes loses up to an interval of them:
+    // fewer KV round trips, paid for in durability
+    flush_interval: Option<Duration>,
+}
+
+impl Config {
+    fn load() -> Self {
+        let flush_interval = config::millis_or("flush-interval-ms", "COUNTER_FLUSH_INTERVAL_MS", 0);
+        Self {
+            mode: config::parse_or("mode", "COUNTER_MODE", Mode::Buckets),
+            gossip_interval: config::millis_or(
+                "gossip-interval-ms",
+                "COUNTER_GOSSIP_INTERVAL_MS",
+                200,
+            ),
+            cas_retries: config::parse_or("cas-retries", "COUNTER_CAS_RETRIES", 10),
+            cas_backoff: config::millis_or("cas-backoff-ms", "COUNTER_CAS_BACKOFF_MS", 5),
+            cas_max_backoff: config::millis_or(
+                "cas-max-backoff-ms",
+                "COUNTER_CAS_MAX_BACKOFF_MS",
+                200,
+            ),
+            read_timeout: config::millis_or("read-timeout-ms", "COUNTER_READ_TIMEOUT_MS", 200),
+            flush_interval: (!flush_interval.is_zero()).then_some(flush_interval),
+        }
+    }
 }
 
 #[derive(Clone)]
 struct Handler {
     state: Arc<Mutex<NodeState>>,
     kv: Storage,
+    config: Config,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 struct NodeState {
     nodes: Vec<String>,
+    crdt: PnCounter,
+    // last state each peer acknowledged, gossip skips peers that are in sync
+    synced: HashMap<String, PnCounter>,
+    // last value read from every bucket and when, the fallback for
+    // unreachable peers
+    buckets: HashMap<String, (i64, Instant)>,
+    // sync writes so far, makes every one of them unique
+    syncs: u64,
+    // with write-behind, this node's bucket including unflushed adds, and
+    // the value last written to seq-kv
+    bucket: i64,
+    flushed: i64,
+    // whether `bucket` includes what seq-kv held when the node started
+    loaded: bool,
 }
 
 impl Handler {
     fn from_init(runtime: Runtime) -> Self {
         Self {
-            state: Arc::new(Mutex::new(NodeState { nodes: Vec::new() })),
+            state: Arc::new(Mutex::new(NodeState::default())),
             kv: seq_kv(runtime),
+            config: Config::load(),
+        }
+    }
+
+    /// Adds `delta` to `key` with a read-CAS loop. Once `cas_retries`
+    /// conflicts are used up the add fails with `TemporarilyUnavailable`,
+    /// telling the client it definitely didn't happen.
+    async fn add_to_key(&self, key: &str, delta: i64) -> Result<()> {
+        let (_, mut handler) = Context::new();
+        let mut backoff = self.config.cas_backoff;
+        for attempt in 0..=self.config.cas_retries {
+            if attempt > 0 {
+                // up to as much again at random, so racing adds spread out
+                tokio::time::sleep(random::jittered(backoff, backoff)).await;
+                backoff = (backoff * 2).min(self.config.cas_max_backoff);
+            }
+            let counter = match self.get_or_zero(handler.spawn_ctx(), key).await {
+                Ok(counter) => counter,
+                Err(err) => {
+                    info!("error while getting value: {err}");
+                    continue;
+                }
+            };
+            let res = self
+                .kv
+                .cas(
+                    handler.spawn_ctx(),
+                    key.to_string(),
+                    counter,
+                    counter + delta,
+                    true,
+                )
+                .await;
+            match res {
+                Ok(()) => return Ok(()),
+                Err(err) if rpc::error_of(err.as_ref()) == Some(Error::PreconditionFailed) => {
+                    info!("{key} changed since we read {counter}, retrying")
+                }
+                // the write may have happened, retrying could apply it twice
+                Err(err) => return Err(err),
+            }
+        }
+        warn!(
+            "giving up adding {delta} after {} retries",
+            self.config.cas_retries
+        );
+        Err(Box::new(Error::TemporarilyUnavailable))
+    }
+
+    /// Reads the shared counter after a unique write, which keeps seq-kv
+    /// from answering with a state older than everything this node has done.
+    async fn read_shared(&self, node_id: &str) -> Result<i64> {
+        let (_, mut handler) = Context::new();
+        let sync = {
+            let mut s = self.state.lock().unwrap();
+            s.syncs += 1;
+            format!("{node_id}-{}", s.syncs)
+        };
+        self.kv
+            .put(handler.spawn_ctx(), "sync".to_string(), sync)
+            .await?;
+        self.get_or_zero(handler.spawn_ctx(), SHARED_KEY).await
+    }
+
+    /// Sums up every node's bucket, fetched concurrently. A bucket that
+    /// doesn't arrive within `read_timeout` counts with its last known value,
+    /// and the read logs how stale that can make it. Without one the read
+    /// fails with `TemporarilyUnavailable`: leaving the bucket out would
+    /// undercount.
+    async fn read_buckets(&self, runtime: &Runtime, node_id: &str) -> Result<i64> {
+        let nodes = self.state.lock().unwrap().nodes.clone();
+        let fetches: Vec<_> = nodes
+            .into_iter()
+            .map(|node| {
+                let (rt, s, local) = (runtime.clone(), self.clone(), node == node_id);
+                tokio::spawn(async move {
+                    let value = s.fetch_bucket(&rt, &node, local).await;
+                    (node, value)
+                })
+            })
+            .collect();
+
+        let mut sum = 0;
+        let mut staleness = Duration::ZERO;
+        let mut stale = Vec::new();
+        for fetch in fetches {
+            let (node, value) = fetch.await.map_err(|_| Box::new(Error::Crash))?;
+            let mut s = self.state.lock().unwrap();
+            match value {
+                Ok(value) => {
+                    s.buckets.insert(node, (value, Instant::now()));
+                    sum += value;
+                }
+                Err(err) => match s.buckets.get(&node) {
+                    Some((value, at)) => {
+                        info!("using cached bucket of {node}: {err}");
+                        staleness = staleness.max(at.elapsed());
+                        stale.push(node);
+                        sum += value;
+                    }
+                    None => {
+                        warn!("no value for the bucket of {node} yet: {err}");
+                        return Err(Box::new(Error::TemporarilyUnavailable));
+                    }
+                },
+            }
+        }
+        if !stale.is_empty() {
+            warn!("read {sum} with cached buckets of {stale:?}, up to {staleness:?} stale");
+        }
+        Ok(sum)
+    }
+
+    async fn fetch_bucket(&self, runtime: &Runtime, node: &str, local: bool) -> Result<i64> {
+        if !local {
+            let res: Response = rpc::call_with_timeout(
+                runtime,
+                self.config.read_timeout,
+                node,
+                Request::ReadBucket {},
+            )
+            .await?;
+            return match res {
+                Response::ReadBucketOk { value } => Ok(value),
+                _ => Err(Box::new(Error::Crash)),
+            };
+        }
+        if self.config.flush_interval.is_some() {
+            return self.local_bucket();
+        }
+        let (ctx, _handle) = Context::with_timeout(self.config.read_timeout);
+        self.get_or_zero(ctx, node).await
+    }
+
+    /// This node's write-behind bucket. Until the adds flushed before a
+    /// restart are loaded it would read too low, so it isn't available yet.
+    fn local_bucket(&self) -> Result<i64> {
+        let state = self.state.lock().unwrap();
+        if !state.loaded {
+            return Err(Box::new(Error::TemporarilyUnavailable));
+        }
+        Ok(state.bucket)
+    }
+
+    /// Reads a counter key. A missing key is a counter nothing was added to
+    /// yet; any other failure is passed on rather than read as 0.
+    async fn get_or_zero(&self, ctx: Context, key: &str) -> Result<i64> {
+        match self.kv.get::<i64>(ctx, key.to_string()).await {
+            Err(err) if rpc::error_of(err.as_ref()) == Some(Error::KeyDoesNotExist) => Ok(0),
+            res => res,
+        }
+    }
+
+    /// Creates this node's bucket unless it exists: a CAS from 0 to 0 that
+    /// may create the key, so a restarted node keeps what it had added.
+    async fn create_bucket(&self, node_id: &str) {
+        let (_, mut handler) = Context::new();
+        let res = self
+            .kv
+            .cas(handler.spawn_ctx(), node_id.to_string(), 0, 0, true)
+            .await;
+        match res {
+            Ok(()) => info!("bucket {node_id} is ready"),
+            Err(err) if rpc::error_of(err.as_ref()) == Some(Error::PreconditionFailed) => {
+                info!("bucket {node_id} already holds adds, keeping them")
+            }
+            // adds create the bucket on their own, reads take a missing one as 0
+            Err(err) => warn!("couldn't create bucket {node_id}: {err}"),
+        }
+    }
+
+    /// Write-behind: picks up whatever the bucket already holds, then keeps
+    /// writing the in-memory bucket back every `flush_interval`. A flush that
+    /// timed out may still land, so each one is a CAS from the value last
+    /// flushed: once an older flush overtook it, it fails instead of
+    /// clobbering a newer one. This node is the only writer of its bucket, so
+    /// a failed precondition just means re-reading what it last wrote.
+    fn start_flushing(&self, node_id: String) {
+        let Some(interval) = self.config.flush_interval else {
+            return;
+        };
+        let s = self.clone();
+        tokio::spawn(async move {
+            // seq-kv may well take longer than a flush interval to answer
+            let mut timeout = interval;
+            let base = loop {
+                let (ctx, _handle) = Context::with_timeout(timeout);
+                match s.get_or_zero(ctx, &node_id).await {
+                    Ok(value) => break value,
+                    Err(err) => warn!("can't load bucket within {timeout:?}, retrying: {err}"),
+                }
+                timeout = (timeout * 2).min(MAX_KV_TIMEOUT);
+                tokio::time::sleep(interval).await;
+            };
+            {
+                let mut state = s.state.lock().unwrap();
+                state.bucket += base;
+                state.flushed = base;
+                state.loaded = true;
+            }
+            loop {
+                tokio::time::sleep(interval).await;
+                let (bucket, flushed) = {
+                    let state = s.state.lock().unwrap();
+                    (state.bucket, state.flushed)
+                };
+                if bucket == flushed {
+                    continue;
+                }
+                let (ctx, _handle) = Context::with_timeout(timeout);
+                match s.kv.cas(ctx, node_id.clone(), flushed, bucket, true).await {
+                    Ok(()) => s.state.lock().unwrap().flushed = bucket,
+                    Err(err) if rpc::error_of(err.as_ref()) == Some(Error::PreconditionFailed) => {
+                        info!("bucket no longer holds {flushed}, re-reading it");
+                        let (ctx, _handle) = Context::with_timeout(timeout);
+                        match s.get_or_zero(ctx, &node_id).await {
+                            Ok(value) => s.state.lock().unwrap().flushed = value,
+                            Err(err) => warn!("re-reading bucket failed: {err}"),
+                        }
+                    }
+                    Err(err) => {
+                        warn!("flushing bucket {bucket} failed: {err}");
+                        timeout = (timeout * 2).min(MAX_KV_TIMEOUT);
+                    }
+                }
+            }
+        });
+    }
+
+    fn start_gossip(&self, runtime: &Runtime) {
+        let (rt, s) = (runtime.clone(), self.clone());
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(s.config.gossip_interval).await;
+                s.gossip(&rt);
+            }
+        });
+    }
+
+    /// Sends our counter to every peer that isn't known to have it yet. The
+    /// reply carries the peer's merged state, so one round trip syncs both.
+    fn gossip(&self, runtime: &Runtime) {
+        let (counter, peers) = {
+            let s = self.state.lock().unwrap();
+            let peers: Vec<String> = s
+                .nodes
+                .iter()
+                .filter(|n| *n != runtime.node_id() && s.synced.get(*n) != Some(&s.crdt))
+                .cloned()
+                .collect();
+            (s.crdt.clone(), peers)
+        };
+        for node in peers {
+            let (rt, s, counter) = (runtime.clone(), self.clone(), counter.clone());
+            tokio::spawn(async move {
+                let res: Result<Response> = rpc::call_with_timeout(
+                    &rt,
+                    s.config.gossip_interval,
+                    node.clone(),
+                    Request::Merge { counter },
+                )
+                .await;
+                if let Ok(Response::MergeOk { counter }) = res {
+                    let mut state = s.state.lock().unwrap();
+                    state.crdt.merge(&counter);
+                    state.synced.insert(node, counter);
+                }
+            });
         }
     }
 }
@@ -57,79 +421,81 @@ impl Node for Handler {
         let node_id = request.dest.clone();
         let (_, mut handler) = tokio_context::context::Context::new();
         match msg {
-            Ok(Request::Init { node_ids, node_id }) => {
-                self.kv
-                    .put(handler.spawn_ctx(), node_id, 0)
+            Ok(Request::Init(Init { node_ids, .. })) if self.config.mode == Mode::Crdt => {
+                self.state.lock().unwrap().nodes = node_ids;
+                self.start_gossip(&runtime);
+                Ok(())
+            }
+            Ok(Request::Add { delta }) if self.config.mode == Mode::Crdt => {
+                self.state.lock().unwrap().crdt.add(&node_id, delta);
+                runtime.reply_ok(request).await
+            }
+            Ok(Request::Read {}) if self.config.mode == Mode::Crdt => {
+                let value = self.state.lock().unwrap().crdt.value();
+                runtime.reply(request, Response::ReadOk { value }).await
+            }
+            Ok(Request::Init(Init { node_ids, .. })) if self.config.mode == Mode::Shared => {
+                self.state.lock().unwrap().nodes = node_ids;
+                Ok(())
+            }
+            Ok(Request::Add { delta }) if self.config.mode == Mode::Shared => {
+                self.add_to_key(SHARED_KEY, delta).await?;
+                runtime.reply_ok(request).await
+            }
+            Ok(Request::Read {}) if self.config.mode == Mode::Shared => {
+                let value = self.read_shared(&node_id).await?;
+                runtime.reply(request, Response::ReadOk { value }).await
+            }
+            Ok(Request::Merge { counter }) => {
+                let merged = {
+                    let mut s = self.state.lock().unwrap();
+                    s.crdt.merge(&counter);
+                    s.crdt.clone()
+                };
+                runtime
+                    .reply(request, Response::MergeOk { counter: merged })
                     .await
-                    .unwrap_or_else(|_| info!("error while writing value"));
+            }
+            Ok(Request::Init(Init { node_ids, node_id }))
+                if self.config.flush_interval.is_some() =>
+            {
                 self.state.lock().unwrap().nodes = node_ids;
+                self.start_flushing(node_id);
                 Ok(())
             }
-            Ok(Request::ReadBucket {}) => {
-                let counter = self
-                    .kv
-                    .get::<u64>(handler.spawn_ctx(), node_id.to_string())
+            Ok(Request::Add { delta }) if self.config.flush_interval.is_some() => {
+                self.state.lock().unwrap().bucket += delta;
+                runtime.reply_ok(request).await
+            }
+            Ok(Request::Read {}) if self.config.flush_interval.is_some() => {
+                let value = self.read_buckets(&runtime, &node_id).await?;
+                runtime.reply(request, Response::ReadOk { value }).await
+            }
+            Ok(Request::ReadBucket {}) if self.config.flush_interval.is_some() => {
+                let value = self.local_bucket()?;
+                runtime
+                    .reply(request, Response::ReadBucketOk { value })
                     .await
-                    .unwrap_or_else(|_| {
-                        info!("error while getting value");
-                        0
-                    });
+            }
+            Ok(Request::Init(Init { node_ids, node_id })) => {
+                self.create_bucket(&node_id).await;
+                self.state.lock().unwrap().nodes = node_ids;
+                Ok(())
+            }
+            Ok(Request::ReadBucket {}) => {
+                // an error reply makes the reader fall back to its cached value
+                let counter = self.get_or_zero(handler.spawn_ctx(), &node_id).await?;
                 runtime
                     .reply(request, Response::ReadBucketOk { value: counter })
                     .await
             }
             Ok(Request::Add { delta }) => {
-                let ctx = handler.spawn_ctx();
-                let counter = self
-                    .kv
-                    .get::<u64>(ctx, node_id.to_string())
-                    .await
-                    .unwrap_or_else(|_| {
-                        info!("error while getting value");
-                        0
-                    });
-                let updated_val = counter + delta;
-                self.kv
-                    .cas(
-                        handler.spawn_ctx(),
-                        node_id.to_string(),
-                        counter,
-                        updated_val,
-                        true,
-                    )
-                    .await
-                    .unwrap_or_else(|_| info!("error while writing value"));
+                self.add_to_key(&node_id, delta).await?;
                 runtime.reply_ok(request).await
             }
             Ok(Request::Read {}) => {
-                let mut sum: u64 = 0;
-                let nodes = self.state.lock().unwrap().nodes.clone();
-                for node in nodes {
-                    if node == node_id {
-                        let ctx = handler.spawn_ctx();
-                        let counter =
-                            self.kv
-                                .get::<u64>(ctx, node.clone())
-                                .await
-                                .unwrap_or_else(|_| {
-                                    info!("error while getting value");
-                                    0
-                                });
-                        sum = sum + counter;
-                    } else {
-                        let mut res = runtime.rpc(node, Request::ReadBucket {}).await.unwrap();
-                        let msg = res.done_with(handler.spawn_ctx()).await.unwrap();
-                        let data = msg.body.as_obj::<Response>()?;
-                        match data {
-                            Response::ReadBucketOk { value } => sum = sum + value,
-                            _ => sum = sum + 0,
-                        }
-                    }
-                }
-
-                runtime
-                    .reply(request, Response::ReadOk { value: sum })
-                    .await
+                let value = self.read_buckets(&runtime, &node_id).await?;
+                runtime.reply(request, Response::ReadOk { value }).await
             }
             _ => done(runtime, request),
         }
@@ -137,11 +503,5 @@ impl Node for Handler {
 }
 
 fn main() -> Result<()> {
-    Runtime::init(try_main())
-}
-
-async fn try_main() -> Result<()> {
-    let r = Runtime::new();
-    let handler = Arc::new(Handler::from_init(r.clone()));
-    r.with_handler(handler).run().await
+    node::run(Handler::from_init)
 }
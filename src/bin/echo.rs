@@ -1,6 +1,5 @@
-use std::sync::Arc;
-
 use async_trait::async_trait;
+use distributed_systems::node;
 use maelstrom::protocol::Message;
 use maelstrom::{done, Node, Result, Runtime};
 
@@ -20,10 +19,5 @@ impl Node for Handler {
 }
 
 fn main() -> Result<()> {
-    Runtime::init(try_main())
-}
-
-async fn try_main() -> Result<()> {
-    let handler = Arc::new(Handler::default());
-    Runtime::new().with_handler(handler).run().await
+    node::run(|_| Handler::default())
 }
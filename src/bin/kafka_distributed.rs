@@ -1,162 +1,365 @@
-use maelstrom::kv::{lin_kv, seq_kv, Storage, KV};
+use maelstrom::kv::{lin_kv, Storage, KV};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use distributed_systems::kafka::{KeyLog, PollLimit, Retention};
+use distributed_systems::protocol::kafka::{Request, Response};
+use distributed_systems::random::{fnv1a, mix};
+use distributed_systems::{config, node, rpc};
 use log::info;
 use maelstrom::protocol::Message;
-use maelstrom::{done, Node, Result, Runtime};
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "snake_case", tag = "type")]
-enum Request {
-    Send {
-        msg: u64,
-        key: String,
-    },
-    Poll {
-        offsets: HashMap<String, u64>,
-    },
-    CommitOffsets {
-        offsets: HashMap<String, u64>,
-    },
-    ListCommittedOffsets {
-        keys: Vec<String>,
-    },
-    Init {
-        node_ids: Vec<String>,
-        node_id: String,
-    },
-}
-
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "snake_case", tag = "type")]
-enum Response {
-    SendOk {
-        offset: i32,
-    },
-    PollOk {
-        msgs: HashMap<String, Vec<Vec<u64>>>,
-    },
-    CommitOffsetsOk {},
-    ListCommittedOffsetsOk {
-        offsets: HashMap<String, u64>,
-    },
-}
+use maelstrom::{done, Error, Node, Result, Runtime};
+use tokio_context::context::Context;
 
+/// Every key is owned by one node, picked by hashing it, which keeps the
+/// key's log in memory and hands out its offsets. Other nodes forward
+/// sends, polls and commits for the key to its owner. Committed offsets are
+/// written to lin-kv, so any node can list them, and so is how far each
+/// key's offsets have been handed out.
 #[derive(Clone)]
 struct Handler {
     kv: Storage,
+    state: Arc<Mutex<NodeState>>,
+    // held while reserving offsets in lin-kv, so sends waiting for the same
+    // block don't reserve one each
+    reserving: Arc<tokio::sync::Mutex<()>>,
+    config: Config,
 }
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
-struct Log {
-    offset: u64,
-    message: u64,
+#[derive(Clone)]
+struct Config {
+    poll_limit: PollLimit,
+    retention: Retention,
+    retention_check: Duration,
+    /// How long a forwarded request waits for the owner before the client
+    /// gets a timeout.
+    forward_timeout: Duration,
+    /// Offsets an owner reserves in lin-kv at a time. Larger blocks cost
+    /// fewer writes, but leave a larger gap when the owner restarts.
+    offset_block: u64,
 }
 
+impl Config {
+    fn load() -> Self {
+        Self {
+            poll_limit: PollLimit {
+                max_msgs: config::parse_or("max-poll-msgs", "KAFKA_MAX_POLL_MSGS", 100),
+                max_bytes: config::parse_or("max-poll-bytes", "KAFKA_MAX_POLL_BYTES", 0),
+            },
+            retention: Retention::load(),
+            retention_check: config::millis_or(
+                "retention-check-ms",
+                "KAFKA_RETENTION_CHECK_MS",
+                1000,
+            ),
+            forward_timeout: config::millis_or(
+                "forward-timeout-ms",
+                "KAFKA_FORWARD_TIMEOUT_MS",
+                1000,
+            ),
+            offset_block: config::parse_or("offset-block", "KAFKA_OFFSET_BLOCK", 100).max(1),
+        }
+    }
+}
+
+#[derive(Default)]
+struct NodeState {
+    // only the keys this node owns
+    logs: HashMap<String, KeyLog>,
+    // offsets below these are reserved for this node in lin-kv
+    reserved: HashMap<String, u64>,
+    commited_offsets: HashMap<String, u64>,
+    started: bool,
+}
+
+const COMMITED_OFFSETS_PREFIX: &str = "commited_offsets";
+const NEXT_OFFSET_PREFIX: &str = "next_offset";
+
+// per-key values of other nodes' keys, by owner
+type ByOwner<T> = HashMap<String, HashMap<String, T>>;
+
 impl Handler {
     fn from_init(runtime: Runtime) -> Self {
         Self {
             kv: lin_kv(runtime),
+            state: Arc::new(Mutex::new(NodeState::default())),
+            reserving: Arc::default(),
+            config: Config::load(),
+        }
+    }
+
+    /// The node owning `key`. Every node has to pick the same one, whatever
+    /// build it runs, so this takes a fixed hash rather than `std`'s.
+    fn owner<'a>(&self, runtime: &'a Runtime, key: &str) -> &'a str {
+        let nodes = runtime.nodes();
+        &nodes[(mix(fnv1a(key.as_bytes())) % nodes.len() as u64) as usize]
+    }
+
+    /// Splits per-key values into the ones this node owns and the rest,
+    /// grouped by owner.
+    fn split<T>(
+        &self,
+        runtime: &Runtime,
+        values: HashMap<String, T>,
+    ) -> (HashMap<String, T>, ByOwner<T>) {
+        let mut local = HashMap::new();
+        let mut remote = ByOwner::new();
+        for (key, value) in values {
+            let owner = self.owner(runtime, &key);
+            if owner == runtime.node_id() {
+                local.insert(key, value);
+            } else {
+                remote
+                    .entry(owner.to_string())
+                    .or_default()
+                    .insert(key, value);
+            }
+        }
+        (local, remote)
+    }
+
+    /// Sends `request` to every owner concurrently and collects the replies.
+    async fn forward_all(
+        &self,
+        runtime: &Runtime,
+        requests: Vec<(String, Request)>,
+    ) -> Result<Vec<Response>> {
+        let calls: Vec<_> = requests
+            .into_iter()
+            .map(|(owner, request)| {
+                let (rt, timeout) = (runtime.clone(), self.config.forward_timeout);
+                tokio::spawn(async move {
+                    rpc::call_with_timeout::<Response>(&rt, timeout, owner, request).await
+                })
+            })
+            .collect();
+        let mut responses = Vec::new();
+        for call in calls {
+            let res = call.await.map_err(|_| Box::new(Error::Crash))?;
+            responses.push(res?);
+        }
+        Ok(responses)
+    }
+
+    async fn poll(
+        &self,
+        runtime: &Runtime,
+        offsets: HashMap<String, u64>,
+    ) -> Result<HashMap<String, Vec<Vec<u64>>>> {
+        let (local, remote) = self.split(runtime, offsets);
+        let mut msgs = HashMap::new();
+        {
+            let state = self.state.lock().unwrap();
+            for (key, from) in local {
+                let Some(log) = state.logs.get(&key) else {
+                    continue;
+                };
+                let entries = log.poll(from, self.config.poll_limit);
+                if !entries.is_empty() {
+                    let mapped_logs = entries.iter().map(|(o, m)| vec![*o, *m]).collect();
+                    msgs.insert(key, mapped_logs);
+                }
+            }
+        }
+        let requests = remote
+            .into_iter()
+            .map(|(owner, offsets)| (owner, Request::Poll { offsets }))
+            .collect();
+        for res in self.forward_all(runtime, requests).await? {
+            if let Response::PollOk { msgs: theirs } = res {
+                msgs.extend(theirs);
+            }
+        }
+        Ok(msgs)
+    }
+
+    /// Appends `msg` to a key this node owns. Its offsets come from blocks
+    /// reserved in lin-kv, so an owner that lost its log in a restart
+    /// carries on after every offset it handed out before, not at 0.
+    async fn append(&self, key: &str, msg: u64) -> Result<u64> {
+        loop {
+            if let Some(offset) = self.append_reserved(key, msg) {
+                return Ok(offset);
+            }
+            let _reserving = self.reserving.lock().await;
+            // another send may have reserved a block while we waited
+            if let Some(offset) = self.append_reserved(key, msg) {
+                return Ok(offset);
+            }
+            let from = self.reserve(key).await?;
+            let mut state = self.state.lock().unwrap();
+            state.logs.entry(key.to_string()).or_default().skip_to(from);
+            state
+                .reserved
+                .insert(key.to_string(), from + self.config.offset_block);
+        }
+    }
+
+    /// Appends `msg` if the offset it gets is reserved already.
+    fn append_reserved(&self, key: &str, msg: u64) -> Option<u64> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let reserved = *state.reserved.get(key)?;
+        let log = state.logs.get_mut(key)?;
+        (log.next_offset() < reserved).then(|| log.append(msg))
+    }
+
+    /// Reserves the next `offset_block` offsets of `key` in lin-kv and
+    /// returns the first one.
+    async fn reserve(&self, key: &str) -> Result<u64> {
+        let (_, mut handler) = Context::new();
+        let key = format!("{NEXT_OFFSET_PREFIX}_{key}");
+        loop {
+            let from = match self.kv.get::<u64>(handler.spawn_ctx(), key.clone()).await {
+                Ok(from) => from,
+                Err(err) if rpc::error_of(err.as_ref()) == Some(Error::KeyDoesNotExist) => 0,
+                Err(err) => return Err(err),
+            };
+            let until = from + self.config.offset_block;
+            let res = self
+                .kv
+                .cas(handler.spawn_ctx(), key.clone(), from, until, true)
+                .await;
+            match res {
+                Ok(()) => return Ok(from),
+                // a reservation from before a restart landed late
+                Err(err) if rpc::error_of(err.as_ref()) == Some(Error::PreconditionFailed) => {
+                    info!("{key} moved past {from}, retrying")
+                }
+                Err(err) => return Err(err),
+            }
         }
     }
+
+    /// Commits only move forward. The owner keeps the latest commit of its
+    /// keys, for compaction, and writes it through to lin-kv.
+    async fn commit(&self, runtime: &Runtime, offsets: HashMap<String, u64>) -> Result<()> {
+        let (local, remote) = self.split(runtime, offsets);
+        let local: Vec<_> = {
+            let mut state = self.state.lock().unwrap();
+            local
+                .into_iter()
+                .map(|(key, offset)| {
+                    let committed = state.commited_offsets.entry(key.clone()).or_insert(offset);
+                    *committed = (*committed).max(offset);
+                    (key, *committed)
+                })
+                .collect()
+        };
+        let writes: Vec<_> = local
+            .into_iter()
+            .map(|(key, offset)| {
+                let s = self.clone();
+                tokio::spawn(async move { s.write_commit(&key, offset).await })
+            })
+            .collect();
+        for write in writes {
+            write.await.map_err(|_| Box::new(Error::Crash))??;
+        }
+        let requests = remote
+            .into_iter()
+            .map(|(owner, offsets)| (owner, Request::CommitOffsets { offsets }))
+            .collect();
+        self.forward_all(runtime, requests).await?;
+        Ok(())
+    }
+
+    /// Raises the committed offset of `key` in lin-kv to `offset`. Commits
+    /// handled concurrently race for the key, so a plain write could put an
+    /// older one last; a commit that finds a later one in place is done.
+    async fn write_commit(&self, key: &str, offset: u64) -> Result<()> {
+        let (_, mut handler) = Context::new();
+        let key = format!("{COMMITED_OFFSETS_PREFIX}_{key}");
+        loop {
+            let current = match self.kv.get::<u64>(handler.spawn_ctx(), key.clone()).await {
+                Ok(current) if current >= offset => return Ok(()),
+                Ok(current) => current,
+                // the cas creates the key, whatever it expects
+                Err(err) if rpc::error_of(err.as_ref()) == Some(Error::KeyDoesNotExist) => offset,
+                Err(err) => return Err(err),
+            };
+            let res = self
+                .kv
+                .cas(handler.spawn_ctx(), key.clone(), current, offset, true)
+                .await;
+            match res {
+                Err(err) if rpc::error_of(err.as_ref()) == Some(Error::PreconditionFailed) => {
+                    info!("{key} moved past {current}, retrying")
+                }
+                res => return res,
+            }
+        }
+    }
+
+    // runs on the first message, the retention task has nothing to do before
+    fn start(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.started {
+            return;
+        }
+        state.started = true;
+        if !self.config.retention.keeps_all() {
+            self.start_retention();
+        }
+    }
+
+    fn start_retention(&self) {
+        let s = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(s.config.retention_check).await;
+                let mut guard = s.state.lock().unwrap();
+                let state = &mut *guard;
+                let now = Instant::now();
+                for (key, log) in state.logs.iter_mut() {
+                    let committed = state.commited_offsets.get(key).copied();
+                    log.retain(&s.config.retention, committed, now);
+                }
+            }
+        });
+    }
 }
 
 #[async_trait]
 impl Node for Handler {
     async fn process(&self, runtime: Runtime, request: Message) -> Result<()> {
         let msg: Result<Request> = request.body.as_obj();
-        let logs_prefix = String::from("logs");
-        let latest_offsets_prefix = String::from("latest_offsets");
-        let commited_offsets_prefix = String::from("commited_offsets");
-        let (_, mut handler) = tokio_context::context::Context::new();
+        self.start();
+        let (_, mut handler) = Context::new();
         match msg {
             Ok(Request::Send { msg, key }) => {
-                let mut offset = self
-                    .kv
-                    .get::<i32>(
-                        handler.spawn_ctx(),
-                        format!("{latest_offsets_prefix}_{key}"),
+                let owner = self.owner(&runtime, &key);
+                if owner != runtime.node_id() {
+                    let res: Response = rpc::call_with_timeout(
+                        &runtime,
+                        self.config.forward_timeout,
+                        owner,
+                        Request::Send { msg, key },
                     )
-                    .await
-                    .unwrap_or_else(|_| {
-                        info!("error while getting value");
-                        0
-                    });
-
-                while let Err(_) = self
-                    .kv
-                    .cas(
-                        handler.spawn_ctx(),
-                        format!("{latest_offsets_prefix}_{key}"),
-                        offset - 1,
-                        offset,
-                        true,
-                    )
-                    .await
-                {
-                    offset += 1
+                    .await?;
+                    return runtime.reply(request, res).await;
                 }
-
-                self.kv
-                    .put(
-                        handler.spawn_ctx(),
-                        format!("{logs_prefix}_{key}_{offset}"),
-                        msg,
-                    )
-                    .await
-                    .unwrap_or_else(|_| info!("error while writing value"));
+                let offset = self.append(&key, msg).await?;
                 runtime.reply(request, Response::SendOk { offset }).await
             }
             Ok(Request::Poll { offsets }) => {
-                //                let mut key_logs = HashMap::new();
-                let mut result_map: HashMap<String, Vec<Vec<u64>>> = HashMap::new();
-                for (key, mut off) in offsets {
-                    let cloned_key = key.clone();
-                    while let Ok(val) = self
-                        .kv
-                        .get::<u64>(
-                            handler.spawn_ctx(),
-                            format!("{logs_prefix}_{cloned_key}_{off}"),
-                        )
-                        .await
-                    {
-                        if let Some(logs) = result_map.get_mut(&key) {
-                            logs.push(vec![off, val]);
-                        } else {
-                            result_map.insert(key.clone(), vec![vec![off, val]]);
-                        };
-                        off += 1;
-                    }
-                }
-                runtime
-                    .reply(request, Response::PollOk { msgs: result_map })
-                    .await
+                let msgs = self.poll(&runtime, offsets).await?;
+                runtime.reply(request, Response::PollOk { msgs }).await
             }
             Ok(Request::CommitOffsets { offsets }) => {
-                for (key, offset) in offsets {
-                    self.kv
-                        .put(
-                            handler.spawn_ctx(),
-                            format!("{commited_offsets_prefix}_{key}"),
-                            offset,
-                        )
-                        .await
-                        .unwrap_or_else(|_| info!("error while writing value"));
-                }
+                self.commit(&runtime, offsets).await?;
                 runtime.reply(request, Response::CommitOffsetsOk {}).await
             }
             Ok(Request::ListCommittedOffsets { keys }) => {
                 let mut keys_offsets = HashMap::new();
-                let keys = keys.clone();
                 for key in keys {
                     if let Ok(commited_offset) = self
                         .kv
                         .get::<u64>(
                             handler.spawn_ctx(),
-                            format!("{commited_offsets_prefix}_{key}"),
+                            format!("{COMMITED_OFFSETS_PREFIX}_{key}"),
                         )
                         .await
                     {
@@ -178,11 +381,5 @@ impl Node for Handler {
 }
 
 fn main() -> Result<()> {
-    Runtime::init(try_main())
-}
-
-async fn try_main() -> Result<()> {
-    let r = Runtime::new();
-    let handler = Arc::new(Handler::from_init(r.clone()));
-    r.with_handler(handler).run().await
+    node::run(Handler::from_init)
 }
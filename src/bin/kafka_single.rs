@@ -1,167 +1,217 @@
 use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
+use tokio::task::block_in_place;
 
 use async_trait::async_trait;
-use log::info;
+use distributed_systems::kafka::store::{Fsync, Store, StoreConfig};
+use distributed_systems::kafka::{KeyLog, PollLimit, Retention};
+use distributed_systems::protocol::kafka::{Request, Response};
+use distributed_systems::{config, node};
+use log::{error, warn};
 use maelstrom::protocol::Message;
-use maelstrom::{done, Node, Result, Runtime};
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "snake_case", tag = "type")]
-enum Request {
-    Send {
-        msg: u64,
-        key: String,
-    },
-    Poll {
-        offsets: HashMap<String, u64>,
-    },
-    CommitOffsets {
-        offsets: HashMap<String, u64>,
-    },
-    ListCommittedOffsets {
-        keys: Vec<String>,
-    },
-    Init {
-        node_ids: Vec<String>,
-        node_id: String,
-    },
-}
-
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "snake_case", tag = "type")]
-enum Response {
-    SendOk {
-        offset: u64,
-    },
-    PollOk {
-        msgs: HashMap<String, Vec<Vec<u64>>>,
-    },
-    CommitOffsetsOk {},
-    ListCommittedOffsetsOk {
-        offsets: HashMap<String, u64>,
-    },
-}
+use maelstrom::{done, Error, Node, Result, Runtime};
 
 #[derive(Clone)]
 struct Handler {
     state: Arc<Mutex<NodeState>>,
+    config: Config,
 }
 
-#[derive(Clone, Default, Debug)]
-struct Log {
-    offset: u64,
-    message: u64,
-}
-
-#[derive(Clone, Default)]
-struct NodeState {
-    logs: HashMap<String, Vec<Log>>,
-    latest_offsets: HashMap<String, u64>,
-    commited_offsets: HashMap<String, u64>,
+#[derive(Clone)]
+struct Config {
+    poll_limit: PollLimit,
+    /// Where the logs are persisted, under a directory per node. Unset keeps
+    /// everything in memory.
+    data_dir: Option<PathBuf>,
+    store: StoreConfig,
+    fsync_interval: Duration,
+    retention: Retention,
+    retention_check: Duration,
 }
 
-impl NodeState {
-    fn get_logs_from_offset(&self, key: &str, offset: u64) -> Option<Vec<Log>> {
-        if let Some(logs) = self.logs.get(key) {
-            if let Some(start_index) = logs.iter().position(|log| log.offset == offset) {
-                let end_index = start_index + 3;
-                let end_index = if end_index > logs.len() {
-                    logs.len()
-                } else {
-                    end_index
-                };
-                let selected_logs = logs[start_index..end_index].to_vec();
-
-                return Some(selected_logs);
-            }
+impl Config {
+    fn load() -> Self {
+        let defaults = StoreConfig::default();
+        Self {
+            poll_limit: PollLimit {
+                max_msgs: config::parse_or("max-poll-msgs", "KAFKA_MAX_POLL_MSGS", 100),
+                max_bytes: config::parse_or("max-poll-bytes", "KAFKA_MAX_POLL_BYTES", 0),
+            },
+            data_dir: config::get("data-dir", "KAFKA_DATA_DIR").map(PathBuf::from),
+            store: StoreConfig {
+                segment_bytes: config::parse_or(
+                    "segment-bytes",
+                    "KAFKA_SEGMENT_BYTES",
+                    defaults.segment_bytes,
+                ),
+                index_interval: config::parse_or(
+                    "index-interval-bytes",
+                    "KAFKA_INDEX_INTERVAL_BYTES",
+                    defaults.index_interval,
+                ),
+                fsync: config::parse_or("fsync", "KAFKA_FSYNC", Fsync::default()),
+            },
+            fsync_interval: config::millis_or("fsync-interval-ms", "KAFKA_FSYNC_INTERVAL_MS", 100),
+            retention: Retention::load(),
+            retention_check: config::millis_or(
+                "retention-check-ms",
+                "KAFKA_RETENTION_CHECK_MS",
+                1000,
+            ),
         }
-        None
     }
 }
 
+#[derive(Default)]
+struct NodeState {
+    logs: HashMap<String, KeyLog>,
+    commited_offsets: HashMap<String, u64>,
+    store: Option<Store>,
+    started: bool,
+}
+
 impl Handler {
     fn from_init() -> Self {
         Self {
             state: Arc::new(Mutex::new(NodeState::default())),
+            config: Config::load(),
+        }
+    }
+
+    /// Runs on the first message, once the node id is known: replaces the
+    /// in-memory state with what the store holds and starts the background
+    /// tasks.
+    fn start(&self, runtime: &Runtime, state: &mut NodeState) -> Result<()> {
+        if state.started {
+            return Ok(());
+        }
+        if let Some(dir) = &self.config.data_dir {
+            let dir = dir.join(runtime.node_id());
+            let (store, recovered) = block_in_place(|| Store::open(dir, self.config.store))?;
+            state.logs = recovered.logs;
+            state.commited_offsets = recovered.committed;
+            state.store = Some(store);
+            if self.config.store.fsync == Fsync::Interval {
+                self.start_syncing();
+            }
         }
+        if !self.config.retention.keeps_all() {
+            self.start_retention();
+        }
+        state.started = true;
+        Ok(())
     }
+
+    fn start_retention(&self) {
+        let s = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(s.config.retention_check).await;
+                let mut guard = s.state.lock().await;
+                let state = &mut *guard;
+                let now = Instant::now();
+                for (key, log) in state.logs.iter_mut() {
+                    let committed = state.commited_offsets.get(key).copied();
+                    let start = log.retain(&s.config.retention, committed, now);
+                    if let Some(store) = state.store.as_mut() {
+                        if let Err(err) = block_in_place(|| store.trim_before(key, start)) {
+                            warn!("can't trim {key}: {err}");
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn start_syncing(&self) {
+        let s = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(s.config.fsync_interval).await;
+                if let Some(store) = s.state.lock().await.store.as_mut() {
+                    if let Err(err) = block_in_place(|| store.sync()) {
+                        warn!("can't sync logs: {err}");
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Runs a store operation. It blocks on the disk, so the worker thread hands
+// its other tasks over first. A write the client can't count on, or a read
+// that can't be served, is reported as a crash.
+fn on_disk<T>(op: impl FnOnce() -> io::Result<T>) -> Result<T> {
+    block_in_place(op).map_err(|err| {
+        error!("store failed: {err}");
+        Box::new(Error::Crash).into()
+    })
 }
 
 #[async_trait]
 impl Node for Handler {
     async fn process(&self, runtime: Runtime, request: Message) -> Result<()> {
         let msg: Result<Request> = request.body.as_obj();
-        let mut state = self.state.lock().await;
+        let mut guard = self.state.lock().await;
+        let state = &mut *guard;
+        self.start(&runtime, state)?;
         match msg {
             Ok(Request::Send { msg, key }) => {
-                let mut offset: u64 = 0;
-                if let Some(&key_offset) = state.latest_offsets.get(&key) {
-                    offset = key_offset + 1;
-                    let log = Log {
-                        offset,
-                        message: msg,
-                    };
-
-                    if let Some(key_logs) = state.logs.get_mut(&key) {
-                        key_logs.push(log)
-                    } else {
-                        state.logs.insert(key.clone(), vec![log]);
-                    };
-                } else {
-                    state.logs.insert(
-                        key.clone(),
-                        vec![Log {
-                            offset,
-                            message: msg,
-                        }],
-                    );
+                let log = state.logs.entry(key.clone()).or_default();
+                if let Some(store) = state.store.as_mut() {
+                    on_disk(|| store.append(&key, log.next_offset(), msg))?;
                 }
-                state.latest_offsets.insert(key, offset);
+                let offset = log.append(msg);
                 runtime.reply(request, Response::SendOk { offset }).await
             }
             Ok(Request::Poll { offsets }) => {
-                let mut key_logs = HashMap::new();
                 let mut result_map = HashMap::new();
                 for (key, off) in offsets {
-                    if let Some(logs) = state.get_logs_from_offset(&key, off) {
-                        key_logs.insert(key.clone(), logs);
+                    let Some(log) = state.logs.get(&key) else {
+                        continue;
                     };
-                }
-
-                for (key, logs) in key_logs {
-                    let mapped_logs: Vec<Vec<u64>> = logs
-                        .into_iter()
-                        .map(|log| vec![log.offset, log.message])
-                        .collect();
-                    result_map.insert(key, mapped_logs);
+                    // retention may have left some of the oldest segment behind
+                    let entries = match &state.store {
+                        Some(store) => on_disk(|| {
+                            store.read(&key, off.max(log.start_offset()), self.config.poll_limit)
+                        })?,
+                        None => log.poll(off, self.config.poll_limit).to_vec(),
+                    };
+                    if !entries.is_empty() {
+                        let mapped_logs: Vec<Vec<u64>> =
+                            entries.iter().map(|(o, m)| vec![*o, *m]).collect();
+                        result_map.insert(key, mapped_logs);
+                    }
                 }
                 runtime
                     .reply(request, Response::PollOk { msgs: result_map })
                     .await
             }
             Ok(Request::CommitOffsets { offsets }) => {
-                state.commited_offsets = offsets;
+                // commits only move forward, a late or replayed one is a no-op
+                for (key, offset) in offsets {
+                    let committed = state.commited_offsets.entry(key).or_insert(offset);
+                    *committed = (*committed).max(offset);
+                }
+                if let Some(store) = &state.store {
+                    on_disk(|| store.save_commits(&state.commited_offsets))?;
+                }
                 runtime.reply(request, Response::CommitOffsetsOk {}).await
             }
             Ok(Request::ListCommittedOffsets { keys }) => {
-                let mut keys_offsets = HashMap::new();
-                let commited_offsets = state.commited_offsets.clone();
-                let keys = keys.clone();
-                for key in keys {
-                    if let Some(val) = commited_offsets.get(&key) {
-                        keys_offsets.insert(key, *val);
-                    }
-                }
+                let offsets = keys
+                    .into_iter()
+                    .filter_map(|key| {
+                        let offset = *state.commited_offsets.get(&key)?;
+                        Some((key, offset))
+                    })
+                    .collect();
                 runtime
-                    .reply(
-                        request,
-                        Response::ListCommittedOffsetsOk {
-                            offsets: commited_offsets,
-                        },
-                    )
+                    .reply(request, Response::ListCommittedOffsetsOk { offsets })
                     .await
             }
             _ => done(runtime, request),
@@ -170,10 +220,5 @@ impl Node for Handler {
 }
 
 fn main() -> Result<()> {
-    Runtime::init(try_main())
-}
-
-async fn try_main() -> Result<()> {
-    let handler = Arc::new(Handler::from_init());
-    Runtime::new().with_handler(handler).run().await
+    node::run(|_| Handler::from_init())
 }
@@ -1,42 +1,29 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
 
 use async_trait::async_trait;
+use distributed_systems::protocol::txn::{self, Txn, TxnOperations};
+use distributed_systems::protocol::Init;
+use distributed_systems::{node, rpc};
+use log::info;
 use maelstrom::protocol::Message;
 use maelstrom::{done, Node, Result, Runtime};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-enum TxnOperations {
-    W,
-    R,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "snake_case", tag = "type")]
 enum Response {
-    TxnOk {
-        txn: Vec<(TxnOperations, u64, Option<u64>)>,
-    },
+    TxnOk { txn: Txn },
     SyncOk {},
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "snake_case", tag = "type")]
 enum Request {
-    Txn {
-        txn: Vec<(TxnOperations, u64, Option<u64>)>,
-    },
-    Sync {
-        changes: HashMap<u64, u64>,
-    },
-    Init {
-        node_ids: Vec<String>,
-        node_id: String,
-    },
+    Txn { txn: Txn },
+    Sync { changes: HashMap<u64, u64> },
+    Init(Init),
 }
 
 #[derive(Clone, Default)]
@@ -56,7 +43,7 @@ impl Node for Handler {
         let msg: Result<Request> = request.body.as_obj();
         let mut state = self.state.lock().await;
         match msg {
-            Ok(Request::Init { node_ids, node_id }) => {
+            Ok(Request::Init(Init { node_ids, node_id })) => {
                 state.nodes = node_ids;
                 state.node_id = node_id;
                 Ok(())
@@ -68,48 +55,25 @@ impl Node for Handler {
                 runtime.reply(request, Response::SyncOk {}).await
             }
             Ok(Request::Txn { txn }) => {
-                let mut response: Vec<(TxnOperations, u64, Option<u64>)> = Vec::new();
-                let mut changes: HashMap<u64, u64> = HashMap::new();
-                for operation in txn {
-                    match operation.0 {
-                        TxnOperations::W => {
-                            let key = operation.1;
-                            let val = operation.2.unwrap();
-                            state.kv.insert(key, val);
-                            changes.insert(key, val);
-                            response.push(operation);
-                        }
-                        TxnOperations::R => {
-                            if let Some(&val) = state.kv.get(&operation.1) {
-                                response.push((TxnOperations::R, operation.1, Some(val)));
-                            } else {
-                                response.push((TxnOperations::R, operation.1, None));
-                            }
-                        }
-                    }
-                }
+                let response = txn::apply(&mut state.kv, txn);
+                let changes: HashMap<u64, u64> = response
+                    .iter()
+                    .filter_map(|op| match op {
+                        (TxnOperations::W, key, Some(val)) => Some((*key, *val)),
+                        _ => None,
+                    })
+                    .collect();
 
                 for node in state.nodes.clone() {
                     if node != state.node_id {
                         let ch = changes.clone();
                         let rt = runtime.clone();
                         tokio::spawn(async move {
-                            let (ctx, _) = tokio_context::context::Context::new();
-                            let mut res = rt
-                                .rpc(
-                                    node,
-                                    Request::Sync {
-                                        changes: ch,
-                                    },
-                                )
-                                .await
-                                .unwrap();
-                            let msg = res.done_with(ctx).await.unwrap();
-                            let data = msg.body.as_obj::<Response>().unwrap();
-                            //                            while let Err(_) = runtime.call_async(node, Request::Sync { changes }) {
-                            //                                tokio::time::sleep(Duration::from_millis(300)).await;
-                            //                                let n = s.state.lock().unwrap().neighbors.clone();
-                            //                            }
+                            let res: Result<Response> =
+                                rpc::call(&rt, node.clone(), Request::Sync { changes: ch }).await;
+                            if let Err(err) = res {
+                                info!("sync with {node} failed: {err}");
+                            }
                         });
                     }
                 }
@@ -124,10 +88,5 @@ impl Node for Handler {
 }
 
 fn main() -> Result<()> {
-    Runtime::init(try_main())
-}
-
-async fn try_main() -> Result<()> {
-    let handler = Arc::new(Handler::default());
-    Runtime::new().with_handler(handler).run().await
+    node::run(|_| Handler::default())
 }
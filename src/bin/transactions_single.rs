@@ -2,27 +2,18 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use distributed_systems::node;
+use distributed_systems::protocol::txn::{self, Txn};
 use maelstrom::protocol::Message;
 use maelstrom::{done, Node, Result, Runtime};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-enum TxnOperations {
-    W,
-    R,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "snake_case", tag = "type")]
 enum Messages {
-    Txn {
-        txn: Vec<(TxnOperations, u64, Option<u64>)>,
-    },
-    TxnOk {
-        txn: Vec<(TxnOperations, u64, Option<u64>)>,
-    },
+    Txn { txn: Txn },
+    TxnOk { txn: Txn },
 }
 
 #[derive(Clone, Default)]
@@ -37,22 +28,7 @@ impl Node for Handler {
         let mut kv = self.kv.lock().await;
         match msg {
             Ok(Messages::Txn { txn }) => {
-                let mut response: Vec<(TxnOperations, u64, Option<u64>)> = Vec::new();
-                for operation in txn {
-                    match operation.0 {
-                        TxnOperations::W => {
-                            kv.insert(operation.1, operation.2.unwrap());
-                            response.push(operation);
-                        }
-                        TxnOperations::R => {
-                            if let Some(&val) = kv.get(&operation.1) {
-                                response.push((TxnOperations::R, operation.1, Some(val)));
-                            } else {
-                                response.push((TxnOperations::R, operation.1, None));
-                            }
-                        }
-                    }
-                }
+                let response = txn::apply(&mut kv, txn);
                 runtime
                     .reply(request, Messages::TxnOk { txn: response })
                     .await
@@ -63,10 +39,5 @@ impl Node for Handler {
 }
 
 fn main() -> Result<()> {
-    Runtime::init(try_main())
-}
-
-async fn try_main() -> Result<()> {
-    let handler = Arc::new(Handler::default());
-    Runtime::new().with_handler(handler).run().await
+    node::run(|_| Handler::default())
 }
@@ -1,6 +1,5 @@
-use std::sync::Arc;
-
 use async_trait::async_trait;
+use distributed_systems::node;
 use maelstrom::protocol::Message;
 use maelstrom::{done, Node, Result, Runtime};
 use serde::{Deserialize, Serialize};
@@ -19,9 +18,10 @@ struct UniqueId {
 impl Node for Handler {
     async fn process(&self, runtime: Runtime, request: Message) -> Result<()> {
         if request.get_type() == "generate" {
-            let mut res = UniqueId::default();
-            res.typ = String::from("generate_ok");
-            res.id = generate_id(&runtime);
+            let res = UniqueId {
+                typ: String::from("generate_ok"),
+                id: generate_id(&runtime),
+            };
             return runtime.reply(request, res).await;
         }
         done(runtime, request)
@@ -29,12 +29,7 @@ impl Node for Handler {
 }
 
 fn main() -> Result<()> {
-    Runtime::init(try_main())
-}
-
-async fn try_main() -> Result<()> {
-    let handler = Arc::new(Handler::default());
-    Runtime::new().with_handler(handler).run().await
+    node::run(|_| Handler::default())
 }
 
 fn generate_id(runtime: &Runtime) -> String {
@@ -0,0 +1,158 @@
+//! Consistency checkers for recorded client histories.
+//!
+//! A history is the list of operations clients performed, each with the
+//! interval it was in flight and how it ended. Every workload module checks
+//! the guarantees the matching binary claims and reports the anomalies it
+//! finds; an empty report means the history is valid.
+//!
+//! The simulator records every client request as a [`Call`]; each module's
+//! `from_call` turns those into its own operations:
+//!
+//! ```no_run
+//! # use distributed_systems::checker::{self, counter};
+//! # use distributed_systems::simulator::Simulator;
+//! # fn run(sim: &Simulator) {
+//! let history = checker::convert(&sim.history(), counter::from_call);
+//! assert!(counter::check(&history).is_empty());
+//! # }
+//! ```
+
+use std::fmt::{Display, Formatter};
+
+use maelstrom::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub mod broadcast;
+pub mod counter;
+pub mod kafka;
+pub mod linearizable;
+pub mod txn;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// The operation definitely took place.
+    Ok,
+    /// The operation definitely did not take place.
+    Fail,
+    /// Unknown, e.g. the client timed out. It may or may not have happened.
+    Info,
+}
+
+impl Outcome {
+    /// How a request answered with an error ended. Timeouts and crashes
+    /// leave it open, every other error is definite.
+    pub fn of_error(err: &Error) -> Self {
+        match err {
+            Error::Timeout | Error::Crash => Outcome::Info,
+            _ => Outcome::Fail,
+        }
+    }
+}
+
+/// One client operation. `start` and `end` are timestamps in any monotonic
+/// unit shared by the whole history; `value` is the completed operation,
+/// with whatever the server returned filled in.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Op<T> {
+    pub process: String,
+    pub start: u64,
+    pub end: u64,
+    pub outcome: Outcome,
+    pub value: T,
+}
+
+pub type History<T> = Vec<Op<T>>;
+
+impl<T> Op<T> {
+    pub fn new(
+        process: impl Into<String>,
+        start: u64,
+        end: u64,
+        outcome: Outcome,
+        value: T,
+    ) -> Self {
+        Self {
+            process: process.into(),
+            start,
+            end,
+            outcome,
+            value,
+        }
+    }
+
+    pub(crate) fn ok(&self) -> bool {
+        self.outcome == Outcome::Ok
+    }
+
+    /// Whether the operation may have taken effect.
+    pub(crate) fn maybe(&self) -> bool {
+        self.outcome != Outcome::Fail
+    }
+}
+
+/// A violated guarantee. `op` is the index of the offending operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Anomaly {
+    pub kind: &'static str,
+    pub op: usize,
+    pub description: String,
+}
+
+impl Anomaly {
+    pub(crate) fn new(kind: &'static str, op: usize, description: impl Into<String>) -> Self {
+        Self {
+            kind,
+            op,
+            description: description.into(),
+        }
+    }
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at op {}: {}", self.kind, self.op, self.description)
+    }
+}
+
+/// A client request as it went over the wire: the body sent to `node` and,
+/// if the request succeeded, the reply body.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Call {
+    pub node: String,
+    pub request: Value,
+    pub reply: Option<Value>,
+}
+
+impl Call {
+    /// The `type` of the request.
+    pub(crate) fn kind(&self) -> &str {
+        self.request["type"].as_str().unwrap_or_default()
+    }
+
+    /// `field` of the reply, `Null` if there is none.
+    pub(crate) fn reply(&self, field: &str) -> &Value {
+        self.reply
+            .as_ref()
+            .map_or(&Value::Null, |reply| &reply[field])
+    }
+}
+
+/// Turns recorded calls into a workload's history. Calls `op` doesn't
+/// recognize, like `init` or `topology`, are left out.
+pub fn convert<T>(history: &History<Call>, op: impl Fn(&Call) -> Option<T>) -> History<T> {
+    history
+        .iter()
+        .filter_map(|call| {
+            let value = op(&call.value)?;
+            Some(Op::new(
+                call.process.clone(),
+                call.start,
+                call.end,
+                call.outcome,
+                value,
+            ))
+        })
+        .collect()
+}
@@ -0,0 +1,83 @@
+//! Checks for `broadcast` histories: reads only return broadcast values, and
+//! the last read of every process, treated as a final read taken after the
+//! cluster settled, contains every acknowledged broadcast.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Anomaly, Call, History};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "f", content = "value")]
+pub enum BroadcastOp {
+    Broadcast(u64),
+    Read(HashSet<u64>),
+}
+
+/// Broadcasts of plain numbers and whole reads. Paged reads (`since` or
+/// `limit`) only show part of the set, so they are left out.
+pub fn from_call(call: &Call) -> Option<BroadcastOp> {
+    match call.kind() {
+        "broadcast" => Some(BroadcastOp::Broadcast(call.request["message"].as_u64()?)),
+        "read" if call.request.get("since").is_none() && call.request.get("limit").is_none() => {
+            let seen = call.reply("messages").as_array().into_iter().flatten();
+            Some(BroadcastOp::Read(seen.filter_map(|m| m.as_u64()).collect()))
+        }
+        _ => None,
+    }
+}
+
+pub fn check(history: &History<BroadcastOp>) -> Vec<Anomaly> {
+    let mut attempted = HashSet::new();
+    let mut acked = Vec::new();
+    let mut final_reads: HashMap<&str, usize> = HashMap::new();
+    for (i, op) in history.iter().enumerate() {
+        match &op.value {
+            BroadcastOp::Broadcast(value) if op.maybe() => {
+                attempted.insert(*value);
+                if op.ok() {
+                    acked.push((i, *value));
+                }
+            }
+            BroadcastOp::Read(_) if op.ok() => {
+                let last = final_reads.entry(&op.process).or_insert(i);
+                if history[*last].end < op.end {
+                    *last = i;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut anomalies = Vec::new();
+    for (i, op) in history.iter().enumerate() {
+        if let BroadcastOp::Read(seen) = &op.value {
+            for value in seen.difference(&attempted) {
+                anomalies.push(Anomaly::new(
+                    "unexpected",
+                    i,
+                    format!("read {value}, which was never broadcast"),
+                ));
+            }
+        }
+    }
+
+    let mut reads: Vec<usize> = final_reads.into_values().collect();
+    reads.sort_unstable();
+    for i in reads {
+        let (BroadcastOp::Read(seen), start) = (&history[i].value, history[i].start) else {
+            continue;
+        };
+        for (j, value) in &acked {
+            if history[*j].end < start && !seen.contains(value) {
+                anomalies.push(Anomaly::new(
+                    "lost",
+                    i,
+                    format!("final read misses {value}, acknowledged by op {j}"),
+                ));
+            }
+        }
+    }
+    anomalies
+}
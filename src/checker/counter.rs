@@ -0,0 +1,66 @@
+//! Checks for `g-counter` (and PN-counter) histories: every read must fall
+//! between the smallest and largest value the counter could have had while
+//! the read was in flight.
+
+use serde::{Deserialize, Serialize};
+
+use super::{Anomaly, Call, History};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "f", content = "value")]
+pub enum CounterOp {
+    Add(i64),
+    Read(i64),
+}
+
+pub fn from_call(call: &Call) -> Option<CounterOp> {
+    match call.kind() {
+        "add" => Some(CounterOp::Add(call.request["delta"].as_i64()?)),
+        "read" => Some(CounterOp::Read(
+            call.reply("value").as_i64().unwrap_or_default(),
+        )),
+        _ => None,
+    }
+}
+
+pub fn check(history: &History<CounterOp>) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    for (i, op) in history.iter().enumerate() {
+        let CounterOp::Read(value) = op.value else {
+            continue;
+        };
+        if !op.ok() {
+            continue;
+        }
+
+        let (mut lower, mut upper) = (0i64, 0i64);
+        for add in history.iter().filter(|add| add.maybe()) {
+            let CounterOp::Add(delta) = add.value else {
+                continue;
+            };
+            // definitely applied before the read started
+            let certain = add.ok() && add.end < op.start;
+            // could have been applied before the read returned
+            let possible = add.start < op.end;
+            if certain {
+                lower += delta;
+                upper += delta;
+            } else if possible {
+                if delta < 0 {
+                    lower += delta;
+                } else {
+                    upper += delta;
+                }
+            }
+        }
+
+        if value < lower || value > upper {
+            anomalies.push(Anomaly::new(
+                "out-of-bounds",
+                i,
+                format!("read {value}, expected between {lower} and {upper}"),
+            ));
+        }
+    }
+    anomalies
+}
@@ -0,0 +1,187 @@
+//! Checks for `kafka` histories: every key behaves like an append-only log
+//! with monotonic offsets, and committed offsets never move backwards.
+
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Anomaly, Call, History};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "f")]
+pub enum KafkaOp {
+    Send {
+        key: String,
+        msg: u64,
+        offset: Option<u64>,
+    },
+    Poll {
+        msgs: HashMap<String, Vec<(u64, u64)>>,
+    },
+    Commit {
+        offsets: HashMap<String, u64>,
+    },
+    List {
+        offsets: HashMap<String, u64>,
+    },
+}
+
+pub fn from_call(call: &Call) -> Option<KafkaOp> {
+    match call.kind() {
+        "send" => Some(KafkaOp::Send {
+            key: call.request["key"].as_str()?.to_string(),
+            msg: call.request["msg"].as_u64()?,
+            offset: call.reply("offset").as_u64(),
+        }),
+        "poll" => Some(KafkaOp::Poll {
+            msgs: decode_or_default(call.reply("msgs")),
+        }),
+        "commit_offsets" => Some(KafkaOp::Commit {
+            offsets: decode_or_default(&call.request["offsets"]),
+        }),
+        "list_committed_offsets" => Some(KafkaOp::List {
+            offsets: decode_or_default(call.reply("offsets")),
+        }),
+        _ => None,
+    }
+}
+
+// a failed call has no reply to decode, and its value is never looked at
+fn decode_or_default<T>(value: &Value) -> T
+where
+    T: DeserializeOwned + Default,
+{
+    serde_json::from_value(value.clone()).unwrap_or_default()
+}
+
+pub fn check(history: &History<KafkaOp>) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+
+    // (key, offset) -> (msg, op that told us)
+    let mut log: HashMap<(String, u64), (u64, usize)> = HashMap::new();
+    let mut observe =
+        |anomalies: &mut Vec<Anomaly>, i: usize, key: &str, offset: u64, msg: u64| match log
+            .get(&(key.to_string(), offset))
+        {
+            Some(&(other, j)) if other != msg => anomalies.push(Anomaly::new(
+                "inconsistent-offset",
+                i,
+                format!("{key}@{offset} is {msg}, but op {j} saw {other}"),
+            )),
+            Some(_) => {}
+            None => {
+                log.insert((key.to_string(), offset), (msg, i));
+            }
+        };
+
+    for (i, op) in history.iter().enumerate() {
+        if !op.ok() {
+            continue;
+        }
+        match &op.value {
+            KafkaOp::Send {
+                key,
+                msg,
+                offset: Some(offset),
+            } => observe(&mut anomalies, i, key, *offset, *msg),
+            KafkaOp::Poll { msgs } => {
+                for (key, entries) in msgs {
+                    for pair in entries.windows(2) {
+                        if pair[1].0 <= pair[0].0 {
+                            anomalies.push(Anomaly::new(
+                                "nonmonotonic-poll",
+                                i,
+                                format!("{key}: offset {} follows {}", pair[1].0, pair[0].0),
+                            ));
+                        }
+                    }
+                    for &(offset, msg) in entries {
+                        observe(&mut anomalies, i, key, offset, msg);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    anomalies.extend(lost_writes(history));
+    anomalies.extend(commit_regressions(history));
+    anomalies
+}
+
+/// Acknowledged sends that a poll skipped over.
+fn lost_writes(history: &History<KafkaOp>) -> Vec<Anomaly> {
+    let mut acked: HashMap<&str, Vec<u64>> = HashMap::new();
+    for op in history.iter().filter(|op| op.ok()) {
+        if let KafkaOp::Send {
+            key,
+            offset: Some(offset),
+            ..
+        } = &op.value
+        {
+            acked.entry(key).or_default().push(*offset);
+        }
+    }
+
+    let mut anomalies = Vec::new();
+    for (i, op) in history.iter().enumerate() {
+        let KafkaOp::Poll { msgs } = &op.value else {
+            continue;
+        };
+        if !op.ok() {
+            continue;
+        }
+        for (key, entries) in msgs {
+            let Some(offsets) = acked.get(key.as_str()) else {
+                continue;
+            };
+            for pair in entries.windows(2) {
+                for offset in offsets {
+                    if pair[0].0 < *offset && *offset < pair[1].0 {
+                        anomalies.push(Anomaly::new(
+                            "lost-write",
+                            i,
+                            format!("{key}: acknowledged offset {offset} skipped"),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    anomalies
+}
+
+/// Committed offsets listed after a commit or an earlier listing completed
+/// must not be lower than what that operation established.
+fn commit_regressions(history: &History<KafkaOp>) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    for (i, op) in history.iter().enumerate() {
+        let KafkaOp::List { offsets: listed } = &op.value else {
+            continue;
+        };
+        if !op.ok() {
+            continue;
+        }
+        for (j, prior) in history.iter().enumerate() {
+            if !prior.ok() || prior.end >= op.start {
+                continue;
+            }
+            let known = match &prior.value {
+                KafkaOp::Commit { offsets } | KafkaOp::List { offsets } => offsets,
+                _ => continue,
+            };
+            for (key, offset) in known {
+                if let Some(now) = listed.get(key).filter(|now| *now < offset) {
+                    anomalies.push(Anomaly::new(
+                        "commit-regression",
+                        i,
+                        format!("{key} listed as {now} after op {j} established {offset}"),
+                    ));
+                }
+            }
+        }
+    }
+    anomalies
+}
@@ -0,0 +1,142 @@
+//! Linearizability of a single read/write/cas register, as served by
+//! `lin-kv`. Histories of multi-key workloads are checked one key at a time.
+//!
+//! Uses the Wing & Gong search with memoization of visited
+//! `(linearized ops, register state)` pairs, which is plenty for the
+//! histories produced in tests.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Anomaly, History, Outcome};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "f")]
+pub enum RegisterOp<T> {
+    /// `None` when the key did not exist.
+    Read {
+        value: Option<T>,
+    },
+    Write {
+        value: T,
+    },
+    Cas {
+        from: T,
+        to: T,
+    },
+}
+
+struct Candidate<'a, T> {
+    index: usize,
+    start: u64,
+    /// `u64::MAX` for operations that never returned.
+    end: u64,
+    required: bool,
+    op: &'a RegisterOp<T>,
+}
+
+struct Search<'a, T> {
+    ops: Vec<Candidate<'a, T>>,
+    visited: HashSet<(Vec<u64>, Option<T>)>,
+    /// Most operations linearized so far and the first one that got stuck there.
+    deepest: (usize, usize),
+}
+
+pub fn check<T>(history: &History<RegisterOp<T>>) -> Vec<Anomaly>
+where
+    T: Clone + Eq + Hash,
+{
+    let ops: Vec<Candidate<T>> = history
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| match op.outcome {
+            Outcome::Ok => true,
+            // an unacknowledged read tells us nothing
+            Outcome::Info => !matches!(op.value, RegisterOp::Read { .. }),
+            Outcome::Fail => false,
+        })
+        .map(|(index, op)| Candidate {
+            index,
+            start: op.start,
+            end: if op.ok() { op.end } else { u64::MAX },
+            required: op.ok(),
+            op: &op.value,
+        })
+        .collect();
+
+    let mut search = Search {
+        visited: HashSet::new(),
+        deepest: (0, ops.first().map_or(0, |c| c.index)),
+        ops,
+    };
+    let done = vec![0u64; search.ops.len().div_ceil(64)];
+    if search.linearize(done, None, 0) {
+        return Vec::new();
+    }
+    vec![Anomaly::new(
+        "not-linearizable",
+        search.deepest.1,
+        format!(
+            "no valid order after linearizing {} operations",
+            search.deepest.0
+        ),
+    )]
+}
+
+impl<T> Search<'_, T>
+where
+    T: Clone + Eq + Hash,
+{
+    fn linearize(&mut self, done: Vec<u64>, state: Option<T>, depth: usize) -> bool {
+        let is_done = |i: usize| done[i / 64] & (1 << (i % 64)) != 0;
+        let pending: Vec<usize> = (0..self.ops.len()).filter(|i| !is_done(*i)).collect();
+        if pending.iter().all(|i| !self.ops[*i].required) {
+            return true;
+        }
+
+        // only operations that started before every pending one finished can go next
+        let horizon = pending.iter().map(|i| self.ops[*i].end).min().unwrap();
+        for i in pending {
+            let candidate = &self.ops[i];
+            if candidate.start > horizon {
+                continue;
+            }
+            let Some(next) = apply(candidate.op, &state) else {
+                continue;
+            };
+            let mut next_done = done.clone();
+            next_done[i / 64] |= 1 << (i % 64);
+            if !self.visited.insert((next_done.clone(), next.clone())) {
+                continue;
+            }
+            if self.linearize(next_done, next, depth + 1) {
+                return true;
+            }
+        }
+
+        if depth > self.deepest.0 {
+            let stuck = (0..self.ops.len())
+                .filter(|i| !is_done(*i) && self.ops[*i].required)
+                .min_by_key(|i| self.ops[*i].end)
+                .unwrap();
+            self.deepest = (depth, self.ops[stuck].index);
+        }
+        false
+    }
+}
+
+/// The register state after `op`, or `None` if `op` cannot happen in `state`.
+fn apply<T>(op: &RegisterOp<T>, state: &Option<T>) -> Option<Option<T>>
+where
+    T: Clone + Eq,
+{
+    match op {
+        RegisterOp::Read { value } if value == state => Some(state.clone()),
+        RegisterOp::Read { .. } => None,
+        RegisterOp::Write { value } => Some(Some(value.clone())),
+        RegisterOp::Cas { from, to } if state.as_ref() == Some(from) => Some(Some(to.clone())),
+        RegisterOp::Cas { .. } => None,
+    }
+}
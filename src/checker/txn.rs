@@ -0,0 +1,106 @@
+//! Read committed for `txn-rw-register` histories, the level
+//! `transactions_distributed` claims.
+//!
+//! Like Maelstrom's workload, it assumes every write to a key carries a value
+//! unique for that key, so each read can be traced back to its writer. It
+//! detects aborted reads (G1a), intermediate reads (G1b), reads of values
+//! nobody wrote, and transactions that do not observe their own writes.
+//! Circular information flow (G1c) is not checked.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use super::{Anomaly, Call, History};
+use crate::protocol::txn::{Txn, TxnOperations};
+
+struct Write {
+    op: usize,
+    committed: bool,
+    /// Overwritten later in the same transaction.
+    intermediate: bool,
+}
+
+/// The transaction with its reads filled in, or as sent if it failed.
+pub fn from_call(call: &Call) -> Option<Txn> {
+    if call.kind() != "txn" {
+        return None;
+    }
+    let txn = match call.reply("txn") {
+        Value::Null => &call.request["txn"],
+        txn => txn,
+    };
+    serde_json::from_value(txn.clone()).ok()
+}
+
+pub fn check(history: &History<Txn>) -> Vec<Anomaly> {
+    let mut writes: HashMap<(u64, u64), Write> = HashMap::new();
+    for (i, op) in history.iter().enumerate() {
+        let mut last: HashMap<u64, u64> = HashMap::new();
+        for (f, key, val) in &op.value {
+            if let (TxnOperations::W, Some(val)) = (f, val) {
+                if let Some(prev) = last.insert(*key, *val) {
+                    if let Some(w) = writes.get_mut(&(*key, prev)) {
+                        w.intermediate = true;
+                    }
+                }
+                writes.insert(
+                    (*key, *val),
+                    Write {
+                        op: i,
+                        committed: op.maybe(),
+                        intermediate: false,
+                    },
+                );
+            }
+        }
+    }
+
+    let mut anomalies = Vec::new();
+    for (i, op) in history.iter().enumerate() {
+        if !op.ok() {
+            continue;
+        }
+        let mut own: HashMap<u64, u64> = HashMap::new();
+        for (f, key, val) in &op.value {
+            match (f, val) {
+                (TxnOperations::W, Some(val)) => {
+                    own.insert(*key, *val);
+                }
+                (TxnOperations::R, read) => {
+                    if let Some(expected) = own.get(key) {
+                        if *read != Some(*expected) {
+                            anomalies.push(Anomaly::new(
+                                "internal",
+                                i,
+                                format!("read {key} = {read:?} after writing {expected}"),
+                            ));
+                        }
+                        continue;
+                    }
+                    let Some(read) = read else { continue };
+                    match writes.get(&(*key, *read)) {
+                        None => anomalies.push(Anomaly::new(
+                            "garbage-read",
+                            i,
+                            format!("read {key} = {read}, which was never written"),
+                        )),
+                        Some(w) if !w.committed => anomalies.push(Anomaly::new(
+                            "G1a",
+                            i,
+                            format!("read {key} = {read} written by failed op {}", w.op),
+                        )),
+                        Some(w) if w.intermediate && w.op != i => anomalies.push(Anomaly::new(
+                            "G1b",
+                            i,
+                            format!("read {key} = {read}, an intermediate write of op {}", w.op),
+                        )),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    anomalies
+}
@@ -0,0 +1,42 @@
+//! Runtime knobs for the binaries. Maelstrom starts nodes with no arguments,
+//! so every setting can come from the environment; a `--flag value` on the
+//! command line takes precedence when running a binary by hand. The
+//! simulator passes none either, it configures nodes through `Config::env`.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Looks up `--flag value` / `--flag=value` in the process arguments, then
+/// the `env` variable.
+pub fn get(flag: &str, env: &str) -> Option<String> {
+    let prefix = format!("--{flag}");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == prefix {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(&prefix).and_then(|v| v.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    std::env::var(env).ok()
+}
+
+/// Same as [`get`], parsed. Unset or unparsable values fall back to `default`.
+pub fn parse_or<T>(flag: &str, env: &str, default: T) -> T
+where
+    T: FromStr,
+{
+    match get(flag, env) {
+        Some(raw) => raw.parse().unwrap_or_else(|_| {
+            log::warn!("ignoring invalid --{flag} / {env}: {raw}");
+            default
+        }),
+        None => default,
+    }
+}
+
+/// A duration given in milliseconds, see [`parse_or`].
+pub fn millis_or(flag: &str, env: &str, default: u64) -> Duration {
+    Duration::from_millis(parse_or(flag, env, default))
+}
@@ -0,0 +1,57 @@
+//! State-based counters. Every node only ever bumps its own entry, and
+//! merging takes the per-node maximum, so replicas converge no matter how
+//! often, in which order or along which path states are exchanged.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Grow-only counter: one monotonic count per node.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn increment(&mut self, node: &str, by: u64) {
+        *self.counts.entry(node.to_string()).or_default() += by;
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn merge(&mut self, other: &GCounter) {
+        for (node, count) in &other.counts {
+            let ours = self.counts.entry(node.clone()).or_default();
+            *ours = (*ours).max(*count);
+        }
+    }
+}
+
+/// Counter supporting decrements, as a pair of grow-only counters.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PnCounter {
+    p: GCounter,
+    n: GCounter,
+}
+
+impl PnCounter {
+    pub fn add(&mut self, node: &str, delta: i64) {
+        if delta >= 0 {
+            self.p.increment(node, delta.unsigned_abs());
+        } else {
+            self.n.increment(node, delta.unsigned_abs());
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.p.value() as i64 - self.n.value() as i64
+    }
+
+    pub fn merge(&mut self, other: &PnCounter) {
+        self.p.merge(&other.p);
+        self.n.merge(&other.n);
+    }
+}
@@ -0,0 +1,108 @@
+//! Compact encoding of integer sets as sorted runs, so a gossip payload
+//! stays small when a node holds tens of thousands of mostly consecutive
+//! ids.
+//!
+//! On the wire a set is a JSON array where a lone id is a number and a run is
+//! an inclusive `[first, last]` pair: `{1, 2, 3, 7}` is `[[1, 3], 7]`. Plain
+//! arrays of ids are therefore valid encodings too.
+
+use std::collections::BTreeSet;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Most ids a decoded set may hold. Receivers iterate over what they are
+/// sent, so a single `[0, u64::MAX]` run must not be accepted.
+pub const MAX_LEN: u128 = 1 << 20;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntervalSet {
+    // sorted, disjoint and never adjacent
+    runs: Vec<(u64, u64)>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Run {
+    One(u64),
+    Many([u64; 2]),
+}
+
+impl IntervalSet {
+    pub fn runs(&self) -> &[(u64, u64)] {
+        &self.runs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// Number of ids in the set, which may not fit a `u64`.
+    pub fn len(&self) -> u128 {
+        self.runs.iter().map(|(a, b)| (b - a) as u128 + 1).sum()
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        let i = self.runs.partition_point(|(_, last)| *last < id);
+        self.runs.get(i).is_some_and(|(first, _)| *first <= id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.runs.iter().flat_map(|(a, b)| *a..=*b)
+    }
+}
+
+impl FromIterator<u64> for IntervalSet {
+    fn from_iter<I: IntoIterator<Item = u64>>(iter: I) -> Self {
+        let sorted: BTreeSet<u64> = iter.into_iter().collect();
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        for id in sorted {
+            match runs.last_mut() {
+                Some((_, last)) if last.checked_add(1) == Some(id) => *last = id,
+                _ => runs.push((id, id)),
+            }
+        }
+        Self { runs }
+    }
+}
+
+impl Serialize for IntervalSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.runs.iter().map(|&(a, b)| {
+            if a == b {
+                Run::One(a)
+            } else {
+                Run::Many([a, b])
+            }
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for IntervalSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut raw: Vec<(u64, u64)> = Vec::<Run>::deserialize(deserializer)?
+            .into_iter()
+            .map(|run| match run {
+                Run::One(id) => (id, id),
+                Run::Many([a, b]) => (a.min(b), a.max(b)),
+            })
+            .collect();
+        // senders may reorder or overlap runs
+        raw.sort_unstable();
+        let mut runs: Vec<(u64, u64)> = Vec::with_capacity(raw.len());
+        for (a, b) in raw {
+            match runs.last_mut() {
+                Some((_, last)) if a <= last.saturating_add(1) => *last = (*last).max(b),
+                _ => runs.push((a, b)),
+            }
+        }
+        let set = Self { runs };
+        if set.len() > MAX_LEN {
+            return Err(D::Error::custom(format!(
+                "set of {} ids, more than {MAX_LEN}",
+                set.len()
+            )));
+        }
+        Ok(set)
+    }
+}
@@ -0,0 +1,183 @@
+//! Per-key logs for the kafka-style brokers.
+
+use std::time::{Duration, Instant};
+
+use crate::config;
+
+pub mod store;
+
+/// How much one `poll` returns for a key. Zero means no limit. The first
+/// message is always returned, however large, so consumers make progress.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PollLimit {
+    pub max_msgs: usize,
+    /// Measured as the encoded `[offset, msg]` pairs.
+    pub max_bytes: usize,
+}
+
+impl PollLimit {
+    /// Whether a poll that already holds `taken` entries gets one more,
+    /// `bytes` being their size with the new one.
+    pub(crate) fn admits(&self, taken: usize, bytes: usize) -> bool {
+        (self.max_msgs == 0 || taken < self.max_msgs)
+            && (self.max_bytes == 0 || taken == 0 || bytes <= self.max_bytes)
+    }
+}
+
+/// How much of each key's log is kept. Zero or `None` means no limit; an
+/// entry goes as soon as any limit drops it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// The newest messages kept.
+    pub max_msgs: usize,
+    /// Measured like [`PollLimit::max_bytes`]. The newest message is kept
+    /// however large.
+    pub max_bytes: usize,
+    pub max_age: Option<Duration>,
+    /// Also drops everything below the key's committed offset, which no
+    /// consumer needs anymore.
+    pub compact: bool,
+}
+
+impl Retention {
+    /// Reads the `--retention-*` / `KAFKA_RETENTION_*` knobs.
+    pub fn load() -> Self {
+        let age = config::millis_or("retention-ms", "KAFKA_RETENTION_MS", 0);
+        Self {
+            max_msgs: config::parse_or("retention-msgs", "KAFKA_RETENTION_MSGS", 0),
+            max_bytes: config::parse_or("retention-bytes", "KAFKA_RETENTION_BYTES", 0),
+            max_age: (!age.is_zero()).then_some(age),
+            compact: config::parse_or("compact-committed", "KAFKA_COMPACT_COMMITTED", false),
+        }
+    }
+
+    /// Whether every log is kept whole.
+    pub fn keeps_all(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// One key's messages ordered by offset. Offsets only grow, but don't have
+/// to be dense: entries may be dropped from the front, and a poll from a
+/// missing offset starts at the next one present.
+#[derive(Clone, Debug, Default)]
+pub struct KeyLog {
+    entries: Vec<(u64, u64)>,
+    // when each entry was added, for age-based retention
+    added: Vec<Instant>,
+    next: u64,
+}
+
+impl KeyLog {
+    /// Appends `msg` and returns its offset.
+    pub fn append(&mut self, msg: u64) -> u64 {
+        let offset = self.next;
+        self.insert(offset, msg);
+        offset
+    }
+
+    /// Adds an entry at a known offset, e.g. when replaying a log. Offsets
+    /// at or below the last one are ignored. Its age counts from now.
+    pub fn insert(&mut self, offset: u64, msg: u64) {
+        if offset < self.next {
+            return;
+        }
+        self.entries.push((offset, msg));
+        self.added.push(Instant::now());
+        self.next = offset + 1;
+    }
+
+    /// The offset the next message gets.
+    pub fn next_offset(&self) -> u64 {
+        self.next
+    }
+
+    /// Makes the next message get at least `offset`, leaving a gap before
+    /// it if need be.
+    pub fn skip_to(&mut self, offset: u64) {
+        self.next = self.next.max(offset);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(u64, u64)] {
+        &self.entries
+    }
+
+    /// The oldest offset still kept, or the next one if nothing is.
+    pub fn start_offset(&self) -> u64 {
+        self.entries
+            .first()
+            .map_or(self.next, |(offset, _)| *offset)
+    }
+
+    /// Drops the entries below `offset`. Nothing else moves: the remaining
+    /// entries and new messages keep their offsets.
+    pub fn trim_before(&mut self, offset: u64) {
+        let n = self.entries.partition_point(|(o, _)| *o < offset);
+        self.entries.drain(..n);
+        self.added.drain(..n);
+    }
+
+    /// Drops what `retention` doesn't keep as of `now`, `committed` being
+    /// the key's committed offset. Returns the new start offset.
+    pub fn retain(&mut self, retention: &Retention, committed: Option<u64>, now: Instant) -> u64 {
+        let mut keep = 0;
+        if retention.max_msgs > 0 {
+            keep = keep.max(self.entries.len().saturating_sub(retention.max_msgs));
+        }
+        if retention.max_bytes > 0 {
+            let mut bytes = 0;
+            for (i, (offset, msg)) in self.entries.iter().enumerate().rev() {
+                bytes += entry_len(*offset, *msg);
+                if bytes > retention.max_bytes {
+                    keep = keep.max((i + 1).min(self.entries.len() - 1));
+                    break;
+                }
+            }
+        }
+        if let Some(age) = retention.max_age {
+            keep = keep.max(self.added.partition_point(|t| now.duration_since(*t) > age));
+        }
+        let mut start = self
+            .entries
+            .get(keep)
+            .map_or(self.next, |(offset, _)| *offset);
+        if let (true, Some(committed)) = (retention.compact, committed) {
+            start = start.max(committed);
+        }
+        self.trim_before(start);
+        self.start_offset()
+    }
+
+    /// Entries from the first offset `>= from`, within `limit`.
+    pub fn poll(&self, from: u64, limit: PollLimit) -> &[(u64, u64)] {
+        let start = self.entries.partition_point(|(offset, _)| *offset < from);
+        let rest = &self.entries[start..];
+        let mut bytes = 0;
+        let end = rest
+            .iter()
+            .enumerate()
+            .take_while(|(i, (offset, msg))| {
+                bytes += entry_len(*offset, *msg);
+                limit.admits(*i, bytes)
+            })
+            .count();
+        &rest[..end]
+    }
+}
+
+/// Size of the `[offset,msg]` encoding of an entry.
+pub(crate) fn entry_len(offset: u64, msg: u64) -> usize {
+    encoded_len(offset) + encoded_len(msg) + 3
+}
+
+fn encoded_len(n: u64) -> usize {
+    n.checked_ilog10().unwrap_or(0) as usize + 1
+}
@@ -0,0 +1,438 @@
+//! On-disk storage for the single-node broker.
+//!
+//! Every key gets a directory of segments named after their first offset.
+//! `<base>.log` holds one `[offset,msg]` line per message, and `<base>.index`
+//! is a sparse index of fixed 16-byte `(offset, position)` entries, one
+//! every `index_interval` bytes of log, that reads use to seek into the
+//! segment. Only the newest segment is appended
+//! to; a new one is rolled once it reaches `segment_bytes`, and retention
+//! deletes whole segments from the front. Committed offsets live next to
+//! the logs in `commits.json`.
+//!
+//! On open, the segments are replayed in order. A torn line at the end of
+//! a segment (the node died mid-write) is cut off, and an index that
+//! doesn't match its log is rebuilt from it.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::warn;
+
+use super::{entry_len, KeyLog, PollLimit};
+
+const INDEX_ENTRY: usize = 16;
+
+/// When appended messages are forced to disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fsync {
+    /// On every append, so an acknowledged send survives a crash.
+    #[default]
+    Always,
+    /// Whenever [`Store::sync`] runs, e.g. on a timer. A crash may lose the
+    /// sends since the last sync.
+    Interval,
+    /// Left to the OS.
+    Never,
+}
+
+impl FromStr for Fsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Fsync::Always),
+            "interval" => Ok(Fsync::Interval),
+            "never" => Ok(Fsync::Never),
+            _ => Err(format!("unknown fsync policy: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StoreConfig {
+    /// Size at which the active segment is closed and a new one started.
+    pub segment_bytes: u64,
+    /// Log bytes between two index entries.
+    pub index_interval: u64,
+    pub fsync: Fsync,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 1 << 20,
+            index_interval: 4096,
+            fsync: Fsync::Always,
+        }
+    }
+}
+
+/// What [`Store::open`] found on disk.
+#[derive(Debug, Default)]
+pub struct Recovered {
+    pub logs: HashMap<String, KeyLog>,
+    pub committed: HashMap<String, u64>,
+}
+
+pub struct Store {
+    dir: PathBuf,
+    config: StoreConfig,
+    logs: HashMap<String, SegmentLog>,
+}
+
+impl Store {
+    /// Opens the store in `dir`, creating it if needed, and replays
+    /// everything in it.
+    pub fn open(dir: impl Into<PathBuf>, config: StoreConfig) -> io::Result<(Self, Recovered)> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("logs"))?;
+        let mut store = Self {
+            dir,
+            config,
+            logs: HashMap::new(),
+        };
+        let mut recovered = Recovered::default();
+        for entry in fs::read_dir(store.dir.join("logs"))? {
+            let path = entry?.path();
+            let Some(key) = path.file_name().and_then(|n| n.to_str()).and_then(unescape) else {
+                warn!("skipping unknown entry {}", path.display());
+                continue;
+            };
+            let (log, entries) = SegmentLog::open(path, config)?;
+            store.logs.insert(key.clone(), log);
+            recovered.logs.insert(key, entries);
+        }
+        match fs::read(store.dir.join("commits.json")) {
+            Ok(raw) => recovered.committed = serde_json::from_slice(&raw)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok((store, recovered))
+    }
+
+    pub fn append(&mut self, key: &str, offset: u64, msg: u64) -> io::Result<()> {
+        let log = match self.logs.get_mut(key) {
+            Some(log) => log,
+            None => {
+                let path = self.dir.join("logs").join(escape(key));
+                let (log, _) = SegmentLog::open(path, self.config)?;
+                self.logs.entry(key.to_string()).or_insert(log)
+            }
+        };
+        log.append(offset, msg)
+    }
+
+    /// Replaces the committed offsets on disk. The file is swapped in with
+    /// a rename, so a crash leaves either the old or the new one.
+    pub fn save_commits(&self, committed: &HashMap<String, u64>) -> io::Result<()> {
+        let tmp = self.dir.join("commits.json.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(committed)?)?;
+        if self.config.fsync != Fsync::Never {
+            file.sync_data()?;
+        }
+        fs::rename(tmp, self.dir.join("commits.json"))
+    }
+
+    /// Forces everything appended so far to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        for log in self.logs.values_mut() {
+            log.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the segments of `key` that only hold offsets below `offset`.
+    /// The active segment always stays, so the log may keep a little more
+    /// than asked for.
+    pub fn trim_before(&mut self, key: &str, offset: u64) -> io::Result<()> {
+        match self.logs.get_mut(key) {
+            Some(log) => log.trim_before(offset),
+            None => Ok(()),
+        }
+    }
+
+    /// Entries of `key` from the first offset `>= from`, within `limit`,
+    /// read back from disk. The segment indexes let it seek close to `from`
+    /// instead of scanning the segment from the start.
+    pub fn read(&self, key: &str, from: u64, limit: PollLimit) -> io::Result<Vec<(u64, u64)>> {
+        match self.logs.get(key) {
+            Some(log) => log.read(from, limit),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// One key's segments.
+struct SegmentLog {
+    dir: PathBuf,
+    config: StoreConfig,
+    bases: Vec<u64>,
+    active: Option<Segment>,
+    dirty: bool,
+}
+
+struct Segment {
+    log: File,
+    index: File,
+    size: u64,
+    // log size at the last index entry
+    indexed: Option<u64>,
+}
+
+impl SegmentLog {
+    fn open(dir: PathBuf, config: StoreConfig) -> io::Result<(Self, KeyLog)> {
+        fs::create_dir_all(&dir)?;
+        let mut bases = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "log") {
+                if let Some(base) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                    bases.push(base);
+                }
+            }
+        }
+        bases.sort_unstable();
+
+        let mut entries = KeyLog::default();
+        let mut active = None;
+        for base in &bases {
+            active = Some(recover_segment(&dir, *base, config, &mut entries)?);
+        }
+        let log = Self {
+            dir,
+            config,
+            bases,
+            active,
+            dirty: false,
+        };
+        Ok((log, entries))
+    }
+
+    fn append(&mut self, offset: u64, msg: u64) -> io::Result<()> {
+        let full = self
+            .active
+            .as_ref()
+            .is_none_or(|s| s.size >= self.config.segment_bytes);
+        if full {
+            self.roll(offset)?;
+        }
+        let segment = self.active.as_mut().expect("rolled above");
+        let line = format!("[{offset},{msg}]\n");
+        let (size, indexed) = (segment.size, segment.indexed);
+        let index_len = match indexed {
+            Some(at) if size - at < self.config.index_interval => None,
+            _ => Some(segment.index.metadata()?.len()),
+        };
+        let written = (|| {
+            if index_len.is_some() {
+                segment.index.write_all(&index_entry(offset, size))?;
+                segment.indexed = Some(size);
+            }
+            segment.log.write_all(line.as_bytes())?;
+            segment.size += line.len() as u64;
+            match self.config.fsync {
+                Fsync::Always => segment.log.sync_data(),
+                Fsync::Interval | Fsync::Never => Ok(()),
+            }
+        })();
+        if let Err(err) = written {
+            // the caller doesn't take up `offset`, so neither may the files:
+            // the next append writes it again
+            segment.log.set_len(size)?;
+            if let Some(len) = index_len {
+                segment.index.set_len(len)?;
+            }
+            (segment.size, segment.indexed) = (size, indexed);
+            return Err(err);
+        }
+        if self.config.fsync == Fsync::Interval {
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn roll(&mut self, base: u64) -> io::Result<()> {
+        self.sync()?;
+        let (log, index) = segment_paths(&self.dir, base);
+        self.active = Some(Segment {
+            log: OpenOptions::new().create(true).append(true).open(log)?,
+            index: OpenOptions::new().create(true).append(true).open(index)?,
+            size: 0,
+            indexed: None,
+        });
+        self.bases.push(base);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if let (true, Some(segment)) = (self.dirty, &self.active) {
+            segment.log.sync_data()?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn trim_before(&mut self, offset: u64) -> io::Result<()> {
+        // a segment ends where the next one starts
+        let n = self.bases.windows(2).take_while(|w| w[1] <= offset).count();
+        for base in self.bases.drain(..n) {
+            let (log, index) = segment_paths(&self.dir, base);
+            fs::remove_file(log)?;
+            fs::remove_file(index)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self, from: u64, limit: PollLimit) -> io::Result<Vec<(u64, u64)>> {
+        let first = self.bases.partition_point(|base| *base <= from).max(1) - 1;
+        let mut out = Vec::new();
+        let mut bytes = 0;
+        for base in self.bases.iter().skip(first) {
+            let (log, index) = segment_paths(&self.dir, *base);
+            let start = read_index(&index)?
+                .into_iter()
+                .take_while(|(offset, _)| *offset <= from)
+                .last()
+                .map_or(0, |(_, position)| position);
+            let mut reader = BufReader::new(File::open(log)?);
+            reader.seek(SeekFrom::Start(start))?;
+            for line in reader.lines() {
+                let Ok(entry) = serde_json::from_str::<(u64, u64)>(&line?) else {
+                    break;
+                };
+                if entry.0 < from {
+                    continue;
+                }
+                bytes += entry_len(entry.0, entry.1);
+                if !limit.admits(out.len(), bytes) {
+                    return Ok(out);
+                }
+                out.push(entry);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Replays one segment into `entries`, cutting off a torn tail and
+/// rebuilding the index if it's off, and opens it for appending.
+fn recover_segment(
+    dir: &Path,
+    base: u64,
+    config: StoreConfig,
+    entries: &mut KeyLog,
+) -> io::Result<Segment> {
+    let (log_path, index_path) = segment_paths(dir, base);
+    let mut reader = BufReader::new(File::open(&log_path)?);
+    let mut expected = Vec::new();
+    let mut indexed: Option<u64> = None;
+    let mut size = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line)?;
+        if n == 0 || line.last() != Some(&b'\n') {
+            break;
+        }
+        let Ok((offset, msg)) = serde_json::from_slice::<(u64, u64)>(&line) else {
+            break;
+        };
+        if indexed.is_none_or(|at| size - at >= config.index_interval) {
+            expected.push((offset, size));
+            indexed = Some(size);
+        }
+        entries.insert(offset, msg);
+        size += n as u64;
+    }
+
+    let log = OpenOptions::new().append(true).open(&log_path)?;
+    if log.metadata()?.len() > size {
+        warn!("truncating torn tail of {}", log_path.display());
+        log.set_len(size)?;
+    }
+    if read_index(&index_path).ok().as_ref() != Some(&expected) {
+        warn!("rebuilding {}", index_path.display());
+        let mut index = File::create(&index_path)?;
+        for (offset, position) in &expected {
+            index.write_all(&index_entry(*offset, *position))?;
+        }
+    }
+    let index = OpenOptions::new().append(true).open(&index_path)?;
+    Ok(Segment {
+        log,
+        index,
+        size,
+        indexed,
+    })
+}
+
+fn read_index(path: &Path) -> io::Result<Vec<(u64, u64)>> {
+    let raw = fs::read(path)?;
+    if raw.len() % INDEX_ENTRY != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "torn index entry",
+        ));
+    }
+    Ok(raw
+        .chunks_exact(INDEX_ENTRY)
+        .map(|entry| {
+            let (offset, position) = entry.split_at(8);
+            (
+                u64::from_le_bytes(offset.try_into().unwrap()),
+                u64::from_le_bytes(position.try_into().unwrap()),
+            )
+        })
+        .collect())
+}
+
+fn index_entry(offset: u64, position: u64) -> [u8; INDEX_ENTRY] {
+    let mut entry = [0; INDEX_ENTRY];
+    entry[..8].copy_from_slice(&offset.to_le_bytes());
+    entry[8..].copy_from_slice(&position.to_le_bytes());
+    entry
+}
+
+fn segment_paths(dir: &Path, base: u64) -> (PathBuf, PathBuf) {
+    (
+        dir.join(format!("{base:020}.log")),
+        dir.join(format!("{base:020}.index")),
+    )
+}
+
+// keys become directory names: anything but [A-Za-z0-9_-] as %xx, and the
+// empty key, which would name the `logs` directory itself, as a lone %
+fn escape(key: &str) -> String {
+    if key.is_empty() {
+        return "%".to_string();
+    }
+    key.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'-' => (b as char).to_string(),
+            _ => format!("%{b:02x}"),
+        })
+        .collect()
+}
+
+fn unescape(name: &str) -> Option<String> {
+    if name == "%" {
+        return Some(String::new());
+    }
+    let mut bytes = Vec::new();
+    let mut rest = name.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,151 @@
+//! Stand-in for Maelstrom's `seq-kv` / `lin-kv` services.
+//!
+//! Speaks the same `read` / `write` / `cas` protocol as `maelstrom::kv::Storage`
+//! and is served by the simulator under the service name it was registered
+//! with.
+
+use std::collections::HashMap;
+
+use maelstrom::protocol::{ErrorMessageBody, Message};
+use maelstrom::Error;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::simulator::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consistency {
+    /// Every read observes the latest write.
+    Linearizable,
+    /// Reads observe the store as of any of the last `staleness` writes,
+    /// across all keys, but a node never goes back to an older state than
+    /// one it has observed, and always sees its own writes. So a write
+    /// followed by a read on another key returns that key as of the write.
+    Sequential { staleness: usize },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum Request {
+    Read {
+        key: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+}
+
+pub struct Store {
+    consistency: Consistency,
+    /// Every version a key went through with the write that made it, oldest
+    /// first.
+    versions: HashMap<String, Vec<(usize, Value)>>,
+    /// Number of writes so far; the store as of write `n` holds the versions
+    /// up to it.
+    clock: usize,
+    /// node -> the newest state it has observed.
+    seen: HashMap<String, usize>,
+    rng: Rng,
+}
+
+impl Store {
+    pub fn new(consistency: Consistency, seed: u64) -> Self {
+        Self {
+            consistency,
+            versions: HashMap::new(),
+            clock: 0,
+            seen: HashMap::new(),
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Handles one request and returns the reply addressed back to its sender.
+    pub fn handle(&mut self, request: &Message) -> Message {
+        let body = match request.body.as_obj::<Request>() {
+            Ok(req) => self.apply(&request.src, req),
+            Err(_) => error(Error::MalformedRequest),
+        };
+        let mut reply =
+            maelstrom::protocol::message(request.dest.clone(), request.src.clone(), body)
+                .expect("kv replies are json objects");
+        reply.body.in_reply_to = request.body.msg_id;
+        reply
+    }
+
+    fn apply(&mut self, node: &str, request: Request) -> Value {
+        match request {
+            Request::Read { key } => {
+                let key = key.to_string();
+                match self.read(node, &key) {
+                    Some(value) => json!({"type": "read_ok", "value": value}),
+                    None => error(Error::KeyDoesNotExist),
+                }
+            }
+            Request::Write { key, value } => {
+                self.write(node, key.to_string(), value);
+                json!({"type": "write_ok"})
+            }
+            Request::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => {
+                let key = key.to_string();
+                match self
+                    .versions
+                    .get(&key)
+                    .and_then(|v| v.last())
+                    .map(|(_, v)| v)
+                {
+                    None if !create_if_not_exists => error(Error::KeyDoesNotExist),
+                    Some(current) if *current != from => error(Error::Custom(
+                        Error::PreconditionFailed.code(),
+                        format!("current value {current} is not {from}"),
+                    )),
+                    _ => {
+                        self.write(node, key, to);
+                        json!({"type": "cas_ok"})
+                    }
+                }
+            }
+        }
+    }
+
+    fn read(&mut self, node: &str, key: &str) -> Option<Value> {
+        let at = match self.consistency {
+            Consistency::Linearizable => self.clock,
+            Consistency::Sequential { staleness } => {
+                let seen = self.seen.get(node).copied().unwrap_or(0);
+                let oldest = self.clock.saturating_sub(staleness).max(seen);
+                oldest + (self.rng.next_u64() % (self.clock - oldest + 1) as u64) as usize
+            }
+        };
+        self.seen.insert(node.to_string(), at);
+        self.versions
+            .get(key)?
+            .iter()
+            .rev()
+            .find(|(written, _)| *written <= at)
+            .map(|(_, value)| value.clone())
+    }
+
+    fn write(&mut self, node: &str, key: String, value: Value) {
+        self.clock += 1;
+        let versions = self.versions.entry(key).or_default();
+        versions.push((self.clock, value));
+        self.seen.insert(node.to_string(), self.clock);
+    }
+}
+
+fn error(err: Error) -> Value {
+    json!(ErrorMessageBody::from_error(err))
+}
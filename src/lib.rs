@@ -0,0 +1,12 @@
+pub mod checker;
+pub mod config;
+pub mod crdt;
+pub mod intervals;
+pub mod kafka;
+pub mod kv;
+pub mod node;
+pub mod protocol;
+pub mod random;
+pub mod rpc;
+pub mod simulator;
+pub mod topology;
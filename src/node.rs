@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use maelstrom::{Node, Result, Runtime};
+
+/// Boots a Maelstrom node: sets up the tokio runtime and logger, builds the
+/// handler from the node's `Runtime` and serves stdin until it closes.
+///
+/// ```no_run
+/// # use distributed_systems::node;
+/// # use maelstrom::BlackHoleNode;
+/// fn main() -> maelstrom::Result<()> {
+///     node::run(|_| BlackHoleNode::default())
+/// }
+/// ```
+pub fn run<H, F>(build: F) -> Result<()>
+where
+    H: Node + 'static,
+    F: FnOnce(Runtime) -> H,
+{
+    Runtime::init(async move {
+        let runtime = Runtime::new();
+        let handler = Arc::new(build(runtime.clone()));
+        runtime.with_handler(handler).run().await
+    })
+}
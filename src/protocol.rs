@@ -0,0 +1,14 @@
+//! Message types shared by more than one workload.
+
+use serde::{Deserialize, Serialize};
+
+pub mod kafka;
+pub mod txn;
+
+/// Body of the `init` message, for handlers that need the membership
+/// before `Runtime::node_id()` is available to them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Init {
+    pub node_ids: Vec<String>,
+    pub node_id: String,
+}
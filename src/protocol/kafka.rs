@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Request {
+    Send { msg: u64, key: String },
+    Poll { offsets: HashMap<String, u64> },
+    CommitOffsets { offsets: HashMap<String, u64> },
+    ListCommittedOffsets { keys: Vec<String> },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+#[allow(clippy::enum_variant_names)]
+pub enum Response {
+    SendOk {
+        offset: u64,
+    },
+    PollOk {
+        msgs: HashMap<String, Vec<Vec<u64>>>,
+    },
+    CommitOffsetsOk {},
+    ListCommittedOffsetsOk {
+        offsets: HashMap<String, u64>,
+    },
+}
@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TxnOperations {
+    W,
+    R,
+}
+
+pub type Txn = Vec<(TxnOperations, u64, Option<u64>)>;
+
+/// Executes `txn` against `kv` and returns it with reads filled in.
+pub fn apply(kv: &mut HashMap<u64, u64>, txn: Txn) -> Txn {
+    let mut response: Txn = Vec::new();
+    for operation in txn {
+        match operation.0 {
+            TxnOperations::W => {
+                kv.insert(operation.1, operation.2.unwrap());
+                response.push(operation);
+            }
+            TxnOperations::R => {
+                response.push((TxnOperations::R, operation.1, kv.get(&operation.1).copied()));
+            }
+        }
+    }
+    response
+}
@@ -0,0 +1,34 @@
+//! Cheap randomness for the binaries and the simulator: hashing ids and
+//! spreading out timers. None of it needs to be cryptographically strong.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// One splitmix64 step from `v`, scrambling its bits so that close inputs
+/// give unrelated outputs.
+pub fn mix(v: u64) -> u64 {
+    let mut z = v.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// 64-bit FNV-1a hash of `bytes`. Unlike `std`'s hashers it is fixed, so
+/// every node and every build agrees on it.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// `base` plus up to `jitter` at random, so timers started together on
+/// different nodes or tasks don't fire in lockstep.
+pub fn jittered(base: Duration, jitter: Duration) -> Duration {
+    let millis = jitter.as_millis() as u64;
+    if millis == 0 {
+        return base;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    base + Duration::from_millis(random % (millis + 1))
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use maelstrom::protocol::Message;
+use maelstrom::{Error, Result, Runtime};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_context::context::Context;
+
+/// Calls `to` and decodes the reply body as `T`. Waits for as long as it takes.
+pub async fn call<T>(runtime: &Runtime, to: impl Into<String>, request: impl Serialize) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    // dropping the handle cancels the context, so it has to outlive the call
+    let (ctx, _handle) = Context::new();
+    decode(runtime.call(ctx, to, request).await?)
+}
+
+/// Same as [`call`], but gives up with `Error::Timeout` after `timeout`.
+pub async fn call_with_timeout<T>(
+    runtime: &Runtime,
+    timeout: Duration,
+    to: impl Into<String>,
+    request: impl Serialize,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let (ctx, _handle) = Context::with_timeout(timeout);
+    decode(runtime.call(ctx, to, request).await?)
+}
+
+fn decode<T>(msg: Message) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    msg.body.as_obj::<T>()
+}
+
+/// The maelstrom error behind a failed call: an error reply, or a timeout.
+/// `None` for anything else, e.g. a reply that didn't decode.
+pub fn error_of(err: &(dyn std::error::Error + Send + Sync + 'static)) -> Option<Error> {
+    err.downcast_ref().cloned()
+}
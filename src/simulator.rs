@@ -0,0 +1,497 @@
+//! In-process replacement for the Maelstrom network.
+//!
+//! `maelstrom::Runtime` always talks over stdin/stdout, so every node is
+//! started as a child process of one of our binaries, exactly like Maelstrom
+//! does. Everything between the processes — routing, latency, partitions,
+//! clients — lives in memory here, which lets multi-node scenarios run under
+//! plain `cargo test`.
+//!
+//! Delivery is scheduled on a single queue ordered by `(deliver_at, seq)`.
+//! Latencies come from a seeded PRNG, but `deliver_at` is the wall-clock
+//! arrival time plus that latency, and the nodes are real processes
+//! scheduled by the OS. Runs are therefore not deterministic: the seed fixes
+//! the sequence of latencies, not the interleaving of messages, so tests
+//! should only assert what holds under any delivery order.
+//!
+//! Deterministic scheduling, with a logical clock and one message in flight
+//! per node, needs the handlers driven in this process rather than behind a
+//! `Runtime` bound to stdin/stdout and tokio's wall-clock timers. Until the
+//! binaries are split into a library of handlers that can be, it is out of
+//! scope here.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::warn;
+use maelstrom::protocol::Message;
+use maelstrom::{Error, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::checker::{Call, History, Op, Outcome};
+use crate::kv::{Consistency, Store};
+use crate::random;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub node_count: usize,
+    pub seed: u64,
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// How long a client waits for a reply before giving up with a timeout.
+    pub rpc_timeout: Duration,
+    /// Extra environment passed to every node process.
+    pub env: Vec<(String, String)>,
+    /// KV services reachable by name, e.g. `("seq-kv", Consistency::Sequential { .. })`.
+    pub services: Vec<(String, Consistency)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            node_count: 1,
+            seed: 0,
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(5),
+            rpc_timeout: Duration::from_secs(1),
+            env: Vec::new(),
+            services: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Adds `env` to the environment of every node process.
+    pub fn with_env(mut self, env: &[(&str, &str)]) -> Self {
+        self.env
+            .extend(env.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        self
+    }
+}
+
+/// Message counters collected by the bus.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Messages delivered between two server nodes.
+    pub server_msgs: u64,
+    /// Requests sent by clients.
+    pub client_ops: u64,
+    /// Requests handled by KV services.
+    pub service_msgs: u64,
+    /// Messages dropped because of a partition.
+    pub dropped: u64,
+}
+
+impl Stats {
+    pub fn msgs_per_op(&self) -> f64 {
+        if self.client_ops == 0 {
+            return 0.0;
+        }
+        self.server_msgs as f64 / self.client_ops as f64
+    }
+}
+
+pub struct Simulator {
+    node_ids: Vec<String>,
+    bin: PathBuf,
+    env: Vec<(String, String)>,
+    shared: Arc<Shared>,
+    children: Vec<Child>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    bus: mpsc::UnboundedSender<Message>,
+    state: Mutex<BusState>,
+    next_client: AtomicU64,
+    rpc_timeout: Duration,
+    /// Time zero of the recorded history.
+    epoch: Instant,
+}
+
+#[derive(Default)]
+struct BusState {
+    /// Node id -> partition group. Empty when the network is healthy.
+    partitions: HashMap<String, usize>,
+    /// Node id -> stdin of its current process.
+    inboxes: HashMap<String, mpsc::UnboundedSender<String>>,
+    stats: Stats,
+    pending: HashMap<(String, u64), oneshot::Sender<Message>>,
+    history: History<Call>,
+}
+
+impl Simulator {
+    /// Spawns `config.node_count` copies of `bin` and sends them `init`.
+    pub async fn start(bin: impl AsRef<Path>, config: Config) -> Result<Self> {
+        let (bus, rx) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            bus: bus.clone(),
+            state: Mutex::new(BusState::default()),
+            next_client: AtomicU64::new(1),
+            rpc_timeout: config.rpc_timeout,
+            epoch: Instant::now(),
+        });
+
+        let node_ids: Vec<String> = (0..config.node_count).map(|i| format!("n{i}")).collect();
+        let mut children = Vec::new();
+        let mut tasks = Vec::new();
+        for node_id in &node_ids {
+            let (child, io) = spawn_node(bin.as_ref(), &config.env, node_id, &shared)?;
+            children.push(child);
+            tasks.extend(io);
+        }
+
+        let services = config
+            .services
+            .iter()
+            .map(|(name, consistency)| (name.clone(), Store::new(*consistency, config.seed)))
+            .collect();
+        tasks.push(tokio::spawn(route(
+            rx,
+            services,
+            shared.clone(),
+            Rng::new(config.seed),
+            config.min_latency,
+            config.max_latency,
+        )));
+
+        let sim = Self {
+            node_ids,
+            bin: bin.as_ref().to_path_buf(),
+            env: config.env,
+            shared,
+            children,
+            tasks,
+        };
+
+        for node_id in &sim.node_ids {
+            sim.init(node_id).await?;
+        }
+        Ok(sim)
+    }
+
+    async fn init(&self, node_id: &str) -> Result<()> {
+        let init = json!({"type": "init", "node_id": node_id, "node_ids": self.node_ids});
+        self.client().call(node_id, init, false).await?;
+        Ok(())
+    }
+
+    /// Kills `node` and starts a fresh process in its place, as after a
+    /// crash. Whatever the node kept in memory is gone, the KV services and
+    /// its files on disk are not.
+    pub async fn restart(&mut self, node: &str) -> Result<()> {
+        let i = self
+            .node_ids
+            .iter()
+            .position(|n| n == node)
+            .ok_or_else(|| Box::new(Error::MalformedRequest))?;
+        let _ = self.children[i].kill().await;
+        let (child, io) = spawn_node(&self.bin, &self.env, node, &self.shared)?;
+        self.children[i] = child;
+        self.tasks.extend(io);
+        self.init(node).await
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// Creates a new client with a fresh `cN` id.
+    pub fn client(&self) -> Client {
+        let n = self.shared.next_client.fetch_add(1, Ordering::Relaxed);
+        Client {
+            id: format!("c{n}"),
+            msg_id: AtomicU64::new(1),
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Splits server nodes into isolated groups. Nodes left out of every
+    /// group form one more group of their own. Clients stay connected.
+    pub fn partition(&self, groups: &[&[&str]]) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.partitions.clear();
+        for node in &self.node_ids {
+            let group = groups
+                .iter()
+                .position(|g| g.contains(&node.as_str()))
+                .unwrap_or(groups.len());
+            state.partitions.insert(node.clone(), group);
+        }
+    }
+
+    pub fn heal(&self) {
+        self.shared.state.lock().unwrap().partitions.clear();
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.shared.state.lock().unwrap().stats
+    }
+
+    /// Every client request so far, in the order they completed, with
+    /// timestamps in microseconds since the simulator started. `init` isn't
+    /// included.
+    pub fn history(&self) -> History<Call> {
+        self.shared.state.lock().unwrap().history.clone()
+    }
+
+    pub fn reset_stats(&self) {
+        self.shared.state.lock().unwrap().stats = Stats::default();
+    }
+
+    pub async fn shutdown(mut self) {
+        for child in &mut self.children {
+            let _ = child.kill().await;
+        }
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+pub struct Client {
+    id: String,
+    msg_id: AtomicU64,
+    shared: Arc<Shared>,
+}
+
+impl Client {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Sends `request` to `node` and waits for the matching reply. Maelstrom
+    /// error replies are turned into `maelstrom::Error`. The request and its
+    /// outcome go into the simulator's history.
+    pub async fn rpc<T>(&self, node: &str, request: T) -> Result<Message>
+    where
+        T: Serialize,
+    {
+        self.call(node, serde_json::to_value(request)?, true).await
+    }
+
+    async fn call(&self, node: &str, request: Value, record: bool) -> Result<Message> {
+        let start = self.shared.epoch.elapsed();
+        let res = self.send(node, &request).await;
+        if record {
+            let (outcome, reply) = match &res {
+                Ok(msg) => (Outcome::Ok, Some(serde_json::to_value(&msg.body)?)),
+                Err(err) => match err.downcast_ref::<Error>() {
+                    Some(err) => (Outcome::of_error(err), None),
+                    None => (Outcome::Info, None),
+                },
+            };
+            let call = Call {
+                node: node.to_string(),
+                request,
+                reply,
+            };
+            let op = Op::new(
+                self.id.clone(),
+                start.as_micros() as u64,
+                self.shared.epoch.elapsed().as_micros() as u64,
+                outcome,
+                call,
+            );
+            self.shared.state.lock().unwrap().history.push(op);
+        }
+        res
+    }
+
+    async fn send(&self, node: &str, request: &Value) -> Result<Message> {
+        let mut msg = maelstrom::protocol::message(self.id.clone(), node, request)?;
+        let msg_id = self.msg_id.fetch_add(1, Ordering::Relaxed);
+        msg.body.msg_id = msg_id;
+
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.pending.insert((self.id.clone(), msg_id), tx);
+            state.stats.client_ops += 1;
+        }
+        if self.shared.bus.send(msg).is_err() {
+            return Err(Box::new(Error::Crash));
+        }
+
+        let reply = tokio::time::timeout(self.shared.rpc_timeout, rx).await;
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .remove(&(self.id.clone(), msg_id));
+        match reply {
+            Ok(Ok(msg)) if msg.body.is_error() => Err(Box::new(Error::from(&msg.body))),
+            Ok(Ok(msg)) => Ok(msg),
+            Ok(Err(_)) => Err(Box::new(Error::Crash)),
+            Err(_) => Err(Box::new(Error::Timeout)),
+        }
+    }
+}
+
+/// Starts one node process and wires its stdin and stdout to the bus.
+fn spawn_node(
+    bin: &Path,
+    env: &[(String, String)],
+    node_id: &str,
+    shared: &Shared,
+) -> Result<(Child, [JoinHandle<()>; 2])> {
+    let mut child = Command::new(bin)
+        .envs(env.iter().cloned())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(if std::env::var_os("SIMULATOR_LOG").is_some() {
+            Stdio::inherit()
+        } else {
+            Stdio::null()
+        })
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let (inbox, mut lines) = mpsc::unbounded_channel::<String>();
+    let input = tokio::spawn(async move {
+        while let Some(line) = lines.recv().await {
+            if stdin.write_all(line.as_bytes()).await.is_err()
+                || stdin.write_all(b"\n").await.is_err()
+            {
+                break;
+            }
+        }
+    });
+    shared
+        .state
+        .lock()
+        .unwrap()
+        .inboxes
+        .insert(node_id.to_string(), inbox);
+
+    let stdout = child.stdout.take().unwrap();
+    let out = shared.bus.clone();
+    let output = tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match serde_json::from_str::<Message>(&line) {
+                Ok(msg) => {
+                    if out.send(msg).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => warn!("simulator: bad message {line}: {err}"),
+            }
+        }
+    });
+    Ok((child, [input, output]))
+}
+
+async fn route(
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    mut services: HashMap<String, Store>,
+    shared: Arc<Shared>,
+    mut rng: Rng,
+    min_latency: Duration,
+    max_latency: Duration,
+) {
+    let mut queue: BinaryHeap<Reverse<(Instant, u64)>> = BinaryHeap::new();
+    let mut in_flight: HashMap<u64, Message> = HashMap::new();
+    let mut seq: u64 = 0;
+    loop {
+        let next = queue.peek().map(|Reverse((at, _))| *at);
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(msg) => {
+                    let at = Instant::now() + rng.between(min_latency, max_latency);
+                    queue.push(Reverse((at, seq)));
+                    in_flight.insert(seq, msg);
+                    seq += 1;
+                }
+                None => break,
+            },
+            _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                let now = Instant::now();
+                while let Some(Reverse((at, id))) = queue.peek().copied() {
+                    if at > now {
+                        break;
+                    }
+                    queue.pop();
+                    if let Some(msg) = in_flight.remove(&id) {
+                        deliver(&shared, &mut services, msg);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn deliver(shared: &Shared, services: &mut HashMap<String, Store>, msg: Message) {
+    if let Some(store) = services.get_mut(&msg.dest) {
+        shared.state.lock().unwrap().stats.service_msgs += 1;
+        let _ = shared.bus.send(store.handle(&msg));
+        return;
+    }
+
+    let mut state = shared.state.lock().unwrap();
+    if let Some(inbox) = state.inboxes.get(&msg.dest).cloned() {
+        if state.inboxes.contains_key(&msg.src) {
+            let (from, to) = (
+                state.partitions.get(&msg.src),
+                state.partitions.get(&msg.dest),
+            );
+            if from != to {
+                state.stats.dropped += 1;
+                return;
+            }
+            state.stats.server_msgs += 1;
+        }
+        drop(state);
+        if let Ok(line) = serde_json::to_string(&msg) {
+            let _ = inbox.send(line);
+        }
+        return;
+    }
+
+    if let Some(tx) = state
+        .pending
+        .remove(&(msg.dest.clone(), msg.body.in_reply_to))
+    {
+        let _ = tx.send(msg);
+    }
+}
+
+/// Small splitmix64 generator for the simulated latencies.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let z = random::mix(self.0);
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z
+    }
+
+    pub(crate) fn between(&mut self, min: Duration, max: Duration) -> Duration {
+        if max <= min {
+            return min;
+        }
+        let span = (max - min).as_micros() as u64 + 1;
+        min + Duration::from_micros(self.next_u64() % span)
+    }
+}
@@ -0,0 +1,115 @@
+//! Topologies a node can build on its own from the cluster membership,
+//! instead of using the one Maelstrom suggests.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Topology {
+    /// Whatever the `topology` message says.
+    #[default]
+    Given,
+    /// Spanning tree where every node has up to `fanout` children.
+    Tree { fanout: usize },
+    /// Nodes laid out row by row on a square grid, linked to their
+    /// horizontal and vertical neighbours.
+    Grid,
+    /// `hubs` fully connected hubs, every other node attached to one of them.
+    Star { hubs: usize },
+}
+
+impl FromStr for Topology {
+    type Err = String;
+
+    /// `given`, `grid`, `tree[:fanout]` or `star[:hubs]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+        let arg = |default: usize| -> Result<usize, String> {
+            match arg {
+                Some(a) => a
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or(format!("invalid topology argument: {a}")),
+                None => Ok(default),
+            }
+        };
+        match kind {
+            "given" => Ok(Topology::Given),
+            "grid" => Ok(Topology::Grid),
+            "tree" => Ok(Topology::Tree { fanout: arg(4)? }),
+            "star" => Ok(Topology::Star { hubs: arg(1)? }),
+            _ => Err(format!("unknown topology: {s}")),
+        }
+    }
+}
+
+impl Display for Topology {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Topology::Given => write!(f, "given"),
+            Topology::Tree { fanout } => write!(f, "tree:{fanout}"),
+            Topology::Grid => write!(f, "grid"),
+            Topology::Star { hubs } => write!(f, "star:{hubs}"),
+        }
+    }
+}
+
+impl Topology {
+    /// Neighbours of `node_id` among `nodes`. Every node computes the same
+    /// graph, so the result is symmetric. Returns `None` for `Given`.
+    pub fn neighbors(&self, node_id: &str, nodes: &[String]) -> Option<Vec<String>> {
+        let mut nodes = nodes.to_vec();
+        // n2 < n10
+        nodes.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+        let me = nodes.iter().position(|n| n == node_id)?;
+        let n = nodes.len();
+
+        let indexes: Vec<usize> = match *self {
+            Topology::Given => return None,
+            Topology::Tree { fanout } => {
+                let mut v: Vec<usize> = (1..=fanout)
+                    .map(|c| me * fanout + c)
+                    .filter(|c| *c < n)
+                    .collect();
+                if me > 0 {
+                    v.push((me - 1) / fanout);
+                }
+                v
+            }
+            Topology::Grid => {
+                let width = (1..=n).find(|w| w * w >= n).unwrap_or(1);
+                let (row, col) = (me / width, me % width);
+                let mut v = Vec::new();
+                if col > 0 {
+                    v.push(me - 1);
+                }
+                if col + 1 < width && me + 1 < n {
+                    v.push(me + 1);
+                }
+                if row > 0 {
+                    v.push(me - width);
+                }
+                if me + width < n {
+                    v.push(me + width);
+                }
+                v
+            }
+            Topology::Star { hubs } => {
+                let hubs = hubs.min(n);
+                if me < hubs {
+                    (0..hubs)
+                        .filter(|h| *h != me)
+                        .chain((hubs..n).filter(|l| l % hubs == me))
+                        .collect()
+                } else {
+                    vec![me % hubs]
+                }
+            }
+        };
+        Some(indexes.into_iter().map(|i| nodes[i].clone()).collect())
+    }
+}
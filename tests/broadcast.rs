@@ -0,0 +1,450 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use distributed_systems::simulator::{Client, Config, Simulator};
+use serde_json::json;
+
+async fn start(node_count: usize, env: &[(&str, &str)]) -> (Simulator, Client) {
+    let config = Config {
+        node_count,
+        ..Config::default()
+    };
+    start_with(config.with_env(env)).await
+}
+
+/// Starts `config.node_count` nodes that are all neighbors of each other.
+async fn start_with(config: Config) -> (Simulator, Client) {
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_broadcast"), config)
+        .await
+        .unwrap();
+    let client = sim.client();
+    let nodes = sim.node_ids().to_vec();
+    for node in &nodes {
+        let others: Vec<&String> = nodes.iter().filter(|n| *n != node).collect();
+        let topology = json!({ node: others });
+        client
+            .rpc(node, json!({"type": "topology", "topology": topology}))
+            .await
+            .unwrap();
+    }
+    (sim, client)
+}
+
+async fn read(client: &Client, node: &str) -> HashSet<u64> {
+    let res = client.rpc(node, json!({"type": "read"})).await.unwrap();
+    serde_json::from_value(res.body.extra["messages"].clone()).unwrap()
+}
+
+#[tokio::test]
+async fn broadcast_survives_partition() {
+    let (sim, client) = start(3, &[]).await;
+    sim.partition(&[&["n0"], &["n1", "n2"]]);
+    for (i, node) in sim.node_ids().iter().enumerate() {
+        client
+            .rpc(node, json!({"type": "broadcast", "message": i}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    sim.heal();
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    for node in sim.node_ids() {
+        assert_eq!(
+            read(&client, node).await,
+            HashSet::from([0, 1, 2]),
+            "{node}"
+        );
+    }
+    assert!(sim.stats().dropped > 0);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn gossip_goes_quiet_once_converged() {
+    let (sim, client) = start(3, &[]).await;
+    for i in 0..10 {
+        client
+            .rpc("n0", json!({"type": "broadcast", "message": i}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    for node in sim.node_ids() {
+        assert_eq!(read(&client, node).await.len(), 10, "{node}");
+    }
+
+    sim.reset_stats();
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(sim.stats().server_msgs, 0);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn generated_topologies() {
+    let (mut msgs_per_op, mut latencies) = (Vec::new(), Vec::new());
+    for topology in ["given", "tree:3", "star:2", "grid"] {
+        let (sim, client) = start(9, &[("BROADCAST_TOPOLOGY", topology)]).await;
+        sim.reset_stats();
+        let began = Instant::now();
+        for i in 0..9u64 {
+            let node = &sim.node_ids()[i as usize];
+            client
+                .rpc(node, json!({"type": "broadcast", "message": i}))
+                .await
+                .unwrap();
+        }
+
+        let mut pending: Vec<String> = sim.node_ids().to_vec();
+        while !pending.is_empty() {
+            assert!(began.elapsed() < Duration::from_secs(5), "{topology}");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let mut still = Vec::new();
+            for node in pending {
+                if read(&client, &node).await.len() < 9 {
+                    still.push(node);
+                }
+            }
+            pending = still;
+        }
+        // a few 300ms gossip rounds, one per hop
+        let latency = began.elapsed();
+        assert!(latency < Duration::from_secs(2), "{topology}: {latency:?}");
+        latencies.push(latency);
+
+        let stats = sim.stats();
+        msgs_per_op.push(stats.server_msgs as f64 / 9.0);
+        sim.shutdown().await;
+    }
+    // a 9 node tree has 8 edges, the suggested full mesh has 36; how long
+    // values take to cross either depends on the interleaving, so latencies
+    // are only bounded above
+    assert!(msgs_per_op[1] < msgs_per_op[0], "{msgs_per_op:?}");
+}
+
+#[tokio::test]
+async fn batching_window_flushes_before_next_round() {
+    let (sim, client) = start(
+        3,
+        &[
+            ("BROADCAST_GOSSIP_INTERVAL_MS", "10000"),
+            ("BROADCAST_BATCH_WINDOW_MS", "200"),
+        ],
+    )
+    .await;
+    sim.reset_stats();
+    for i in 0..5 {
+        client
+            .rpc("n0", json!({"type": "broadcast", "message": i}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    for node in sim.node_ids() {
+        assert_eq!(read(&client, node).await.len(), 5, "{node}");
+    }
+    // forwarding value by value costs a gossip and an ack per value and peer;
+    // batches may split on the window's boundary, but stay below that
+    assert!(sim.stats().server_msgs < 5 * 2 * 2, "{:?}", sim.stats());
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn eager_push_with_anti_entropy_repair() {
+    let (sim, client) = start(
+        5,
+        &[
+            ("BROADCAST_MODE", "eager"),
+            ("BROADCAST_TOPOLOGY", "tree:2"),
+            ("BROADCAST_GOSSIP_INTERVAL_MS", "500"),
+        ],
+    )
+    .await;
+    client
+        .rpc("n4", json!({"type": "broadcast", "message": 1}))
+        .await
+        .unwrap();
+    // well before the first anti-entropy round
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    for node in sim.node_ids() {
+        assert_eq!(read(&client, node).await, HashSet::from([1]), "{node}");
+    }
+
+    sim.partition(&[&["n4"]]);
+    client
+        .rpc("n4", json!({"type": "broadcast", "message": 2}))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(read(&client, "n0").await, HashSet::from([1]));
+    sim.heal();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    for node in sim.node_ids() {
+        assert_eq!(read(&client, node).await, HashSet::from([1, 2]), "{node}");
+    }
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn repeated_topology_keeps_one_gossip_loop() {
+    let (sim, client) = start(2, &[("BROADCAST_GOSSIP_INTERVAL_MS", "200")]).await;
+    let topology = json!({"n0": ["n1"], "n1": ["n0"]});
+    for _ in 0..5 {
+        client
+            .rpc("n0", json!({"type": "topology", "topology": topology}))
+            .await
+            .unwrap();
+    }
+
+    // with acks cut off n0 keeps retrying, once per round, and timers only
+    // ever fire late: one loop drops at most 1000 / 200 + 1 gossips, five
+    // loops several times that
+    sim.partition(&[&["n0"], &["n1"]]);
+    client
+        .rpc("n0", json!({"type": "broadcast", "message": 1}))
+        .await
+        .unwrap();
+    sim.reset_stats();
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert!(sim.stats().dropped <= 6, "{:?}", sim.stats());
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn gossip_is_acknowledged_with_a_summary() {
+    let (sim, client) = start(1, &[]).await;
+    let ack = client
+        .rpc("n0", json!({"type": "gossip", "seen": [1, 2, 3]}))
+        .await
+        .unwrap();
+    assert_eq!(ack.get_type(), "gossip_ok");
+    assert_eq!(ack.body.extra["count"], 3);
+
+    // the digest only depends on the set, not on how it was built
+    let again = client
+        .rpc("n0", json!({"type": "gossip", "seen": [3, 1]}))
+        .await
+        .unwrap();
+    assert_eq!(again.body.extra["digest"], ack.body.extra["digest"]);
+    assert_eq!(read(&client, "n0").await, HashSet::from([1, 2, 3]));
+    sim.shutdown().await;
+}
+
+/// Broadcasts one value from every node, the duplicates prune the full mesh
+/// down to a spanning tree.
+async fn prune_to_a_tree(sim: &Simulator, client: &Client) {
+    for (i, node) in sim.node_ids().iter().enumerate() {
+        client
+            .rpc(node, json!({"type": "broadcast", "message": i}))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+}
+
+#[tokio::test]
+async fn plumtree_pushes_along_a_spanning_tree() {
+    // no gossip round, and so no ihave, before the test is over
+    let (sim, client) = start(
+        6,
+        &[
+            ("BROADCAST_MODE", "plumtree"),
+            ("BROADCAST_GOSSIP_INTERVAL_MS", "10000"),
+        ],
+    )
+    .await;
+    prune_to_a_tree(&sim, &client).await;
+
+    sim.reset_stats();
+    for (i, node) in sim.node_ids().iter().enumerate() {
+        client
+            .rpc(node, json!({"type": "broadcast", "message": 6 + i}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    for node in sim.node_ids() {
+        assert_eq!(read(&client, node).await.len(), 12, "{node}");
+    }
+    // pushes are one-way: eager push over the full mesh costs 5 + 5 * 4
+    // messages per broadcast, the 5 edges of a tree 5 from any root
+    let msgs_per_op = sim.stats().server_msgs as f64 / 6.0;
+    assert!(msgs_per_op < 6.0, "{:?}", sim.stats());
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn plumtree_repairs_partitions() {
+    let (sim, client) = start(
+        6,
+        &[
+            ("BROADCAST_MODE", "plumtree"),
+            ("BROADCAST_GOSSIP_INTERVAL_MS", "200"),
+        ],
+    )
+    .await;
+    prune_to_a_tree(&sim, &client).await;
+
+    sim.partition(&[&["n0", "n1"], &["n2", "n3", "n4", "n5"]]);
+    for (i, node) in ["n0", "n3"].iter().enumerate() {
+        client
+            .rpc(node, json!({"type": "broadcast", "message": 100 + i}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    sim.heal();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    for node in sim.node_ids() {
+        let values = read(&client, node).await;
+        assert!(values.contains(&100) && values.contains(&101), "{node}");
+    }
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn plumtree_gossip_rounds_keep_the_tree() {
+    // every push times out before its ack is back, so each gossip round
+    // repeats values the neighbors already got over the tree
+    let config = Config {
+        node_count: 6,
+        min_latency: Duration::from_millis(20),
+        max_latency: Duration::from_millis(20),
+        ..Config::default()
+    };
+    let (sim, client) = start_with(config.with_env(&[
+        ("BROADCAST_MODE", "plumtree"),
+        ("BROADCAST_GOSSIP_INTERVAL_MS", "30"),
+        ("BROADCAST_GRAFT_TIMEOUT_MS", "5000"),
+    ]))
+    .await;
+    for i in 0..5 {
+        client
+            .rpc("n0", json!({"type": "broadcast", "message": i}))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // grafting takes seconds, so the value can only make it in time if the
+    // tree edges survived all those rounds
+    let began = Instant::now();
+    client
+        .rpc("n0", json!({"type": "broadcast", "message": 100}))
+        .await
+        .unwrap();
+    let mut pending: Vec<String> = sim.node_ids().to_vec();
+    while !pending.is_empty() {
+        assert!(began.elapsed() < Duration::from_secs(1), "{pending:?}");
+        let mut still = Vec::new();
+        for node in pending {
+            if !read(&client, &node).await.contains(&100) {
+                still.push(node);
+            }
+        }
+        pending = still;
+    }
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn read_pages_through_a_cursor() {
+    let (sim, client) = start(1, &[]).await;
+    for i in 0..5 {
+        client
+            .rpc("n0", json!({"type": "broadcast", "message": i * 10}))
+            .await
+            .unwrap();
+    }
+    let page = |since, limit| json!({"type": "read", "since": since, "limit": limit});
+
+    let res = client.rpc("n0", page(0, 2)).await.unwrap();
+    assert_eq!(res.body.extra["messages"], json!([0, 10]));
+    assert_eq!(res.body.extra["cursor"], 2);
+    let res = client
+        .rpc("n0", json!({"type": "read", "since": 2}))
+        .await
+        .unwrap();
+    assert_eq!(res.body.extra["messages"], json!([20, 30, 40]));
+    assert_eq!(res.body.extra["cursor"], 5);
+
+    // nothing new yet, the cursor stays put
+    let res = client.rpc("n0", page(5, 10)).await.unwrap();
+    assert_eq!(res.body.extra["messages"], json!([]));
+    assert_eq!(res.body.extra["cursor"], 5);
+
+    client
+        .rpc("n0", json!({"type": "broadcast", "message": 50}))
+        .await
+        .unwrap();
+    let res = client.rpc("n0", page(5, 10)).await.unwrap();
+    assert_eq!(res.body.extra["messages"], json!([50]));
+
+    // a plain read still returns everything, without a cursor
+    let res = client.rpc("n0", json!({"type": "read"})).await.unwrap();
+    assert!(res.body.extra.get("cursor").is_none());
+    assert_eq!(read(&client, "n0").await.len(), 6);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn broadcasts_json_payloads() {
+    let (sim, client) = start(3, &[]).await;
+    let event = json!({"kind": "config", "key": "timeout", "value": 30});
+    for message in [event.clone(), json!("hello"), json!(7)] {
+        client
+            .rpc("n0", json!({"type": "broadcast", "message": message}))
+            .await
+            .unwrap();
+    }
+    // same content with reordered keys, and a client id for a payload
+    // that repeats an earlier one
+    let reordered = json!({"value": 30, "key": "timeout", "kind": "config"});
+    client
+        .rpc("n1", json!({"type": "broadcast", "message": reordered}))
+        .await
+        .unwrap();
+    client
+        .rpc(
+            "n2",
+            json!({"type": "broadcast", "message": "hello", "id": 1}),
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    for node in sim.node_ids() {
+        let res = client.rpc(node, json!({"type": "read"})).await.unwrap();
+        let messages = res.body.extra["messages"].as_array().unwrap().clone();
+        assert_eq!(messages.len(), 4, "{node}: {messages:?}");
+        for expected in [&event, &json!("hello"), &json!(7)] {
+            assert!(messages.contains(expected), "{node}: {messages:?}");
+        }
+        let hellos = messages.iter().filter(|m| *m == "hello").count();
+        assert_eq!(hellos, 2, "{node}");
+    }
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn client_ids_dont_collide_with_plain_integers() {
+    let (sim, client) = start(2, &[]).await;
+    for body in [
+        json!({"type": "broadcast", "message": "x", "id": 1}),
+        json!({"type": "broadcast", "message": 1}),
+    ] {
+        client.rpc("n0", body).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    for node in sim.node_ids() {
+        let res = client.rpc(node, json!({"type": "read"})).await.unwrap();
+        let messages = res.body.extra["messages"].as_array().unwrap().clone();
+        assert_eq!(messages.len(), 2, "{node}: {messages:?}");
+        assert!(messages.contains(&json!("x")), "{node}: {messages:?}");
+        assert!(messages.contains(&json!(1)), "{node}: {messages:?}");
+    }
+    sim.shutdown().await;
+}
@@ -0,0 +1,346 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use distributed_systems::checker::broadcast::{self, BroadcastOp};
+use distributed_systems::checker::counter::{self, CounterOp};
+use distributed_systems::checker::kafka::{self, KafkaOp};
+use distributed_systems::checker::linearizable::{self, RegisterOp};
+use distributed_systems::checker::{self, txn, Anomaly, Op, Outcome};
+use distributed_systems::kv::Consistency;
+use distributed_systems::protocol::txn::TxnOperations::{R, W};
+use distributed_systems::simulator::{Client, Config, Simulator};
+use serde_json::{json, Value};
+
+#[test]
+fn txn_read_committed() {
+    let valid = vec![
+        Op::new(
+            "c1",
+            0,
+            1,
+            Outcome::Ok,
+            vec![(W, 1, Some(1)), (R, 1, Some(1))],
+        ),
+        Op::new("c2", 2, 3, Outcome::Ok, vec![(R, 1, Some(1)), (R, 2, None)]),
+    ];
+    assert!(txn::check(&valid).is_empty());
+
+    let aborted = vec![
+        Op::new("c1", 0, 1, Outcome::Fail, vec![(W, 1, Some(1))]),
+        Op::new("c2", 2, 3, Outcome::Ok, vec![(R, 1, Some(1))]),
+    ];
+    assert_eq!(txn::check(&aborted)[0].kind, "G1a");
+
+    let intermediate = vec![
+        Op::new(
+            "c1",
+            0,
+            3,
+            Outcome::Ok,
+            vec![(W, 1, Some(1)), (W, 1, Some(2))],
+        ),
+        Op::new("c2", 1, 2, Outcome::Ok, vec![(R, 1, Some(1))]),
+    ];
+    assert_eq!(txn::check(&intermediate)[0].kind, "G1b");
+}
+
+#[test]
+fn kafka_offsets() {
+    let send = |msg, offset| KafkaOp::Send {
+        key: "k".to_string(),
+        msg,
+        offset: Some(offset),
+    };
+    let poll = |entries: Vec<(u64, u64)>| KafkaOp::Poll {
+        msgs: HashMap::from([("k".to_string(), entries)]),
+    };
+    let valid = vec![
+        Op::new("c1", 0, 1, Outcome::Ok, send(10, 0)),
+        Op::new("c1", 2, 3, Outcome::Ok, send(11, 1)),
+        Op::new("c2", 4, 5, Outcome::Ok, poll(vec![(0, 10), (1, 11)])),
+    ];
+    assert!(kafka::check(&valid).is_empty());
+
+    let duplicate = vec![
+        Op::new("c1", 0, 1, Outcome::Ok, send(10, 0)),
+        Op::new("c2", 0, 1, Outcome::Ok, send(11, 0)),
+    ];
+    assert_eq!(kafka::check(&duplicate)[0].kind, "inconsistent-offset");
+
+    let lost = vec![
+        Op::new("c1", 0, 1, Outcome::Ok, send(10, 0)),
+        Op::new("c1", 0, 1, Outcome::Ok, send(11, 1)),
+        Op::new("c1", 0, 1, Outcome::Ok, send(12, 2)),
+        Op::new("c2", 2, 3, Outcome::Ok, poll(vec![(0, 10), (2, 12)])),
+    ];
+    assert_eq!(kafka::check(&lost)[0].kind, "lost-write");
+
+    let offsets = |offset| HashMap::from([("k".to_string(), offset)]);
+    let regression = vec![
+        Op::new(
+            "c1",
+            0,
+            1,
+            Outcome::Ok,
+            KafkaOp::Commit {
+                offsets: offsets(5),
+            },
+        ),
+        Op::new(
+            "c2",
+            2,
+            3,
+            Outcome::Ok,
+            KafkaOp::List {
+                offsets: offsets(3),
+            },
+        ),
+    ];
+    assert_eq!(kafka::check(&regression)[0].kind, "commit-regression");
+}
+
+#[test]
+fn counter_bounds() {
+    let history = vec![
+        Op::new("c1", 0, 1, Outcome::Ok, CounterOp::Add(2)),
+        Op::new("c2", 2, 5, Outcome::Info, CounterOp::Add(3)),
+        Op::new("c1", 3, 4, Outcome::Ok, CounterOp::Read(5)),
+        Op::new("c1", 6, 7, Outcome::Ok, CounterOp::Read(2)),
+    ];
+    assert!(counter::check(&history).is_empty());
+
+    let lost = vec![
+        Op::new("c1", 0, 1, Outcome::Ok, CounterOp::Add(2)),
+        Op::new("c1", 2, 3, Outcome::Ok, CounterOp::Read(0)),
+    ];
+    assert_eq!(counter::check(&lost)[0].kind, "out-of-bounds");
+}
+
+#[test]
+fn broadcast_final_reads() {
+    let history = vec![
+        Op::new("c1", 0, 1, Outcome::Ok, BroadcastOp::Broadcast(1)),
+        Op::new("c1", 0, 1, Outcome::Ok, BroadcastOp::Broadcast(2)),
+        Op::new(
+            "c2",
+            2,
+            3,
+            Outcome::Ok,
+            BroadcastOp::Read(HashSet::from([1])),
+        ),
+        Op::new(
+            "c2",
+            4,
+            5,
+            Outcome::Ok,
+            BroadcastOp::Read(HashSet::from([1, 2])),
+        ),
+    ];
+    assert!(broadcast::check(&history).is_empty());
+
+    let lost = vec![
+        Op::new("c1", 0, 1, Outcome::Ok, BroadcastOp::Broadcast(1)),
+        Op::new(
+            "c2",
+            2,
+            3,
+            Outcome::Ok,
+            BroadcastOp::Read(HashSet::from([3])),
+        ),
+    ];
+    let kinds: Vec<_> = broadcast::check(&lost).iter().map(|a| a.kind).collect();
+    assert_eq!(kinds, vec!["unexpected", "lost"]);
+}
+
+#[test]
+fn register_linearizability() {
+    let concurrent = vec![
+        Op::new("c1", 0, 10, Outcome::Ok, RegisterOp::Write { value: 1 }),
+        Op::new("c2", 1, 2, Outcome::Ok, RegisterOp::Read { value: Some(1) }),
+        Op::new("c3", 3, 4, Outcome::Ok, RegisterOp::Cas { from: 1, to: 2 }),
+        Op::new("c4", 5, 6, Outcome::Info, RegisterOp::Write { value: 3 }),
+        Op::new("c2", 7, 8, Outcome::Ok, RegisterOp::Read { value: Some(2) }),
+    ];
+    assert!(linearizable::check(&concurrent).is_empty());
+
+    let stale = vec![
+        Op::new("c1", 0, 1, Outcome::Ok, RegisterOp::Write { value: 1 }),
+        Op::new("c1", 2, 3, Outcome::Ok, RegisterOp::Write { value: 2 }),
+        Op::new("c2", 4, 5, Outcome::Ok, RegisterOp::Read { value: Some(1) }),
+    ];
+    let anomalies = linearizable::check(&stale);
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].op, 2);
+}
+
+// every client sends `ops` requests, all clients at once; request `i` of
+// client `c` is `op(c, i)` and goes to node `c + i` (mod n)
+async fn run_concurrently(
+    sim: &Simulator,
+    clients: &[Arc<Client>],
+    ops: usize,
+    op: fn(usize, usize) -> Value,
+) {
+    let nodes = sim.node_ids().to_vec();
+    let tasks: Vec<_> = clients
+        .iter()
+        .enumerate()
+        .map(|(c, client)| {
+            let (client, nodes) = (client.clone(), nodes.clone());
+            tokio::spawn(async move {
+                for i in 0..ops {
+                    let _ = client.rpc(&nodes[(c + i) % nodes.len()], op(c, i)).await;
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+fn clients(sim: &Simulator, n: usize) -> Vec<Arc<Client>> {
+    (0..n).map(|_| Arc::new(sim.client())).collect()
+}
+
+// the last read of every client, plus one at every node from a client of
+// its own, after the run settled
+async fn final_reads(sim: &Simulator, clients: &[Arc<Client>], read: Value) {
+    let nodes = sim.node_ids();
+    for (c, client) in clients.iter().enumerate() {
+        client
+            .rpc(&nodes[c % nodes.len()], read.clone())
+            .await
+            .unwrap();
+    }
+    for node in nodes {
+        sim.client().rpc(node, read.clone()).await.unwrap();
+    }
+}
+
+fn assert_valid(anomalies: Vec<Anomaly>) {
+    assert!(anomalies.is_empty(), "{anomalies:#?}");
+}
+
+#[tokio::test]
+async fn broadcast_run_checks_out() {
+    let config = Config {
+        node_count: 5,
+        ..Config::default()
+    };
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_broadcast"), config)
+        .await
+        .unwrap();
+    let client = sim.client();
+    let nodes = sim.node_ids().to_vec();
+    for node in &nodes {
+        let others: Vec<&String> = nodes.iter().filter(|n| *n != node).collect();
+        client
+            .rpc(
+                node,
+                json!({"type": "topology", "topology": {node: others}}),
+            )
+            .await
+            .unwrap();
+    }
+    let clients = clients(&sim, 3);
+    run_concurrently(&sim, &clients, 20, |c, i| match i % 4 {
+        3 => json!({"type": "read"}),
+        _ => json!({"type": "broadcast", "message": c * 100 + i}),
+    })
+    .await;
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    final_reads(&sim, &clients, json!({"type": "read"})).await;
+
+    let history = checker::convert(&sim.history(), broadcast::from_call);
+    assert_eq!(history.len(), 3 * 20 + 3 + 5);
+    assert_valid(broadcast::check(&history));
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn counter_run_checks_out() {
+    let config = Config {
+        node_count: 3,
+        services: vec![(
+            "seq-kv".to_string(),
+            Consistency::Sequential { staleness: 0 },
+        )],
+        ..Config::default()
+    };
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_counter"), config)
+        .await
+        .unwrap();
+    let clients = clients(&sim, 3);
+    run_concurrently(&sim, &clients, 20, |c, i| match i % 3 {
+        2 => json!({"type": "read"}),
+        _ => json!({"type": "add", "delta": c as i64 * 2 - 1}),
+    })
+    .await;
+    final_reads(&sim, &clients, json!({"type": "read"})).await;
+
+    let history = checker::convert(&sim.history(), counter::from_call);
+    assert_valid(counter::check(&history));
+    sim.shutdown().await;
+}
+
+fn kafka_op(c: usize, i: usize) -> Value {
+    let key = format!("k{}", i % 2);
+    match i % 5 {
+        3 => json!({"type": "poll", "offsets": {"k0": 0, "k1": 0}}),
+        4 if c == 0 => json!({"type": "commit_offsets", "offsets": {key: i / 5}}),
+        4 => json!({"type": "list_committed_offsets", "keys": ["k0", "k1"]}),
+        _ => json!({"type": "send", "key": key, "msg": c * 1000 + i}),
+    }
+}
+
+async fn check_kafka_run(bin: &str, config: Config) {
+    let sim = Simulator::start(bin, config).await.unwrap();
+    let clients = clients(&sim, 3);
+    run_concurrently(&sim, &clients, 30, kafka_op).await;
+    let poll = json!({"type": "poll", "offsets": {"k0": 0, "k1": 0}});
+    final_reads(&sim, &clients, poll).await;
+
+    let history = checker::convert(&sim.history(), kafka::from_call);
+    assert_eq!(history.len(), 3 * 30 + 3 + sim.node_ids().len());
+    assert_valid(kafka::check(&history));
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn kafka_single_run_checks_out() {
+    check_kafka_run(env!("CARGO_BIN_EXE_kafka_single"), Config::default()).await;
+}
+
+#[tokio::test]
+async fn kafka_distributed_run_checks_out() {
+    let config = Config {
+        node_count: 3,
+        services: vec![("lin-kv".to_string(), Consistency::Linearizable)],
+        ..Config::default()
+    };
+    check_kafka_run(env!("CARGO_BIN_EXE_kafka_distributed"), config).await;
+}
+
+#[tokio::test]
+async fn transactions_run_checks_out() {
+    let config = Config {
+        node_count: 3,
+        ..Config::default()
+    };
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_transactions_distributed"), config)
+        .await
+        .unwrap();
+    // every write carries a value unique to its key
+    run_concurrently(&sim, &clients(&sim, 3), 20, |c, i| {
+        let value = c * 1000 + i;
+        json!({"type": "txn", "txn": [["r", i % 3, null], ["w", i % 3, value], ["r", (i + 1) % 3, null]]})
+    })
+    .await;
+
+    let history = checker::convert(&sim.history(), txn::from_call);
+    assert_eq!(history.len(), 3 * 20);
+    assert_valid(txn::check(&history));
+    sim.shutdown().await;
+}
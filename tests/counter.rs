@@ -0,0 +1,261 @@
+use std::time::Duration;
+
+use distributed_systems::checker::{self, counter};
+use distributed_systems::kv::Consistency;
+use distributed_systems::rpc;
+use distributed_systems::simulator::{Client, Config, Simulator};
+use maelstrom::Error;
+use serde_json::json;
+
+async fn start(node_count: usize, env: &[(&str, &str)]) -> (Simulator, Client) {
+    start_with_staleness(node_count, 0, env).await
+}
+
+async fn start_with_staleness(
+    node_count: usize,
+    staleness: usize,
+    env: &[(&str, &str)],
+) -> (Simulator, Client) {
+    let config = Config {
+        node_count,
+        services: vec![("seq-kv".to_string(), Consistency::Sequential { staleness })],
+        ..Config::default()
+    }
+    .with_env(env);
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_counter"), config)
+        .await
+        .unwrap();
+    let client = sim.client();
+    (sim, client)
+}
+
+async fn read(client: &Client, node: &str) -> i64 {
+    let res = client.rpc(node, json!({"type": "read"})).await.unwrap();
+    res.body.extra["value"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn crdt_counter_converges_after_partition() {
+    let (sim, client) = start(3, &[("COUNTER_MODE", "crdt")]).await;
+    sim.partition(&[&["n0"], &["n1", "n2"]]);
+    for (node, delta) in [("n0", 5), ("n1", 3), ("n2", -2), ("n0", 1)] {
+        client
+            .rpc(node, json!({"type": "add", "delta": delta}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(read(&client, "n0").await, 6);
+    assert_eq!(read(&client, "n1").await, 1);
+
+    sim.heal();
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    for node in sim.node_ids() {
+        assert_eq!(read(&client, node).await, 7, "{node}");
+    }
+    assert_eq!(sim.stats().service_msgs, 0);
+
+    // in sync, nothing left to gossip
+    sim.reset_stats();
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(sim.stats().server_msgs, 0, "{:?}", sim.stats());
+    sim.shutdown().await;
+}
+
+/// Fires `count` concurrent adds of 1 at `node`, returns how many succeeded.
+async fn concurrent_adds(sim: &Simulator, node: &str, count: usize) -> i64 {
+    let tasks: Vec<_> = (0..count)
+        .map(|_| {
+            let (client, node) = (sim.client(), node.to_string());
+            tokio::spawn(async move { client.rpc(&node, json!({"type": "add", "delta": 1})).await })
+        })
+        .collect();
+    let mut acked = 0;
+    for task in tasks {
+        match task.await.unwrap() {
+            Ok(_) => acked += 1,
+            Err(err) => assert_eq!(
+                rpc::error_of(err.as_ref()),
+                Some(Error::TemporarilyUnavailable)
+            ),
+        }
+    }
+    acked
+}
+
+#[tokio::test]
+async fn concurrent_adds_retry_their_cas() {
+    let (sim, client) = start(1, &[]).await;
+    assert_eq!(concurrent_adds(&sim, "n0", 20).await, 20);
+    assert_eq!(read(&client, "n0").await, 20);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn exhausted_retries_fail_the_add() {
+    let (sim, client) = start(1, &[("COUNTER_CAS_RETRIES", "0")]).await;
+    let acked = concurrent_adds(&sim, "n0", 20).await;
+    assert!(acked < 20, "no CAS conflict at all");
+    // every add that was acknowledged counted, none of the failed ones did
+    assert_eq!(read(&client, "n0").await, acked);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn read_falls_back_to_cached_buckets() {
+    let (sim, client) = start(3, &[]).await;
+    for (i, node) in sim.node_ids().iter().enumerate() {
+        client
+            .rpc(node, json!({"type": "add", "delta": i + 1}))
+            .await
+            .unwrap();
+    }
+    for node in sim.node_ids() {
+        assert_eq!(read(&client, node).await, 6, "{node}");
+    }
+
+    sim.partition(&[&["n0"], &["n1", "n2"]]);
+    client
+        .rpc("n1", json!({"type": "add", "delta": 10}))
+        .await
+        .unwrap();
+    // n1 and n2 are out of reach, n0 answers with what it saw last
+    assert_eq!(read(&client, "n0").await, 6);
+    assert_eq!(read(&client, "n1").await, 16);
+
+    sim.heal();
+    assert_eq!(read(&client, "n0").await, 16);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn shared_key_reads_see_every_acknowledged_add() {
+    let mut kv_msgs_per_op = Vec::new();
+    for mode in ["shared", "buckets"] {
+        let (sim, client) = start_with_staleness(3, 20, &[("COUNTER_MODE", mode)]).await;
+        // cut off from each other, only seq-kv is left to agree through
+        sim.partition(&[&["n0"], &["n1"], &["n2"]]);
+        sim.reset_stats();
+        let mut total = 0;
+        for round in 1..=5 {
+            for node in sim.node_ids() {
+                client
+                    .rpc(node, json!({"type": "add", "delta": round}))
+                    .await
+                    .unwrap();
+                total += round;
+            }
+            let res = client.rpc("n1", json!({"type": "read"})).await;
+            match res {
+                Ok(res) if mode == "shared" => {
+                    assert_eq!(res.body.extra["value"], total, "round {round}")
+                }
+                // the other buckets are only known through their owners, the
+                // checker below bounds whatever is read anyway
+                Ok(_) => {}
+                Err(err) => assert!(
+                    mode == "buckets"
+                        && rpc::error_of(err.as_ref()) == Some(Error::TemporarilyUnavailable),
+                    "{mode}, round {round}: {err}"
+                ),
+            }
+        }
+        let history = checker::convert(&sim.history(), counter::from_call);
+        let anomalies = counter::check(&history);
+        assert!(anomalies.is_empty(), "{mode}: {anomalies:?}");
+        let stats = sim.stats();
+        kv_msgs_per_op.push(stats.service_msgs as f64 / stats.client_ops as f64);
+        sim.shutdown().await;
+    }
+    // the sync write before every read and the CAS retries on the one key
+    // are what shared pays for being right
+    assert!(kv_msgs_per_op[1] < kv_msgs_per_op[0], "{kv_msgs_per_op:?}");
+}
+
+#[tokio::test]
+async fn write_behind_batches_bucket_writes() {
+    let (sim, client) = start(2, &[("COUNTER_FLUSH_INTERVAL_MS", "100")]).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    sim.reset_stats();
+    for _ in 0..30 {
+        client
+            .rpc("n0", json!({"type": "add", "delta": 1}))
+            .await
+            .unwrap();
+    }
+    // unflushed adds already count, locally and for peers
+    assert_eq!(read(&client, "n0").await, 30);
+    assert_eq!(read(&client, "n1").await, 30);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let stats = sim.stats();
+    assert!(stats.service_msgs > 0, "never flushed");
+    // a write and its reply per flush, instead of a read and a cas per add
+    assert!(stats.service_msgs < 30, "{stats:?}");
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn write_behind_reads_wait_for_the_flushed_bucket() {
+    // seq-kv answers after 40ms, later than a 30ms flush interval: loading
+    // the bucket has to wait longer than that to get it back
+    let config = Config {
+        node_count: 2,
+        min_latency: Duration::from_millis(20),
+        max_latency: Duration::from_millis(20),
+        services: vec![(
+            "seq-kv".to_string(),
+            Consistency::Sequential { staleness: 0 },
+        )],
+        ..Config::default()
+    };
+    let mut sim = Simulator::start(
+        env!("CARGO_BIN_EXE_counter"),
+        config.with_env(&[("COUNTER_FLUSH_INTERVAL_MS", "30")]),
+    )
+    .await
+    .unwrap();
+    let client = sim.client();
+    client
+        .rpc("n0", json!({"type": "add", "delta": 5}))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    sim.restart("n0").await.unwrap();
+    // until the bucket is loaded reading 0 would lose the add made before
+    // the restart, so reads fail instead
+    let err = client.rpc("n0", json!({"type": "read"})).await.unwrap_err();
+    assert_eq!(
+        rpc::error_of(err.as_ref()),
+        Some(Error::TemporarilyUnavailable)
+    );
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(read(&client, "n0").await, 5);
+
+    // flushes that time out may still land, none of them may undo a later one
+    for _ in 0..10 {
+        client
+            .rpc("n0", json!({"type": "add", "delta": 1}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    sim.restart("n0").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(read(&client, "n0").await, 15);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn init_keeps_an_existing_bucket() {
+    let (mut sim, client) = start(2, &[]).await;
+    client
+        .rpc("n0", json!({"type": "add", "delta": 5}))
+        .await
+        .unwrap();
+    sim.restart("n0").await.unwrap();
+    assert_eq!(read(&client, "n0").await, 5);
+    assert_eq!(read(&client, "n1").await, 5);
+    sim.shutdown().await;
+}
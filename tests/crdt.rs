@@ -0,0 +1,32 @@
+use distributed_systems::crdt::PnCounter;
+
+#[test]
+fn pn_counter_merges_in_any_order() {
+    let (mut a, mut b, mut c) = (
+        PnCounter::default(),
+        PnCounter::default(),
+        PnCounter::default(),
+    );
+    a.add("n0", 5);
+    a.add("n0", -2);
+    b.add("n1", 10);
+    c.add("n2", -4);
+
+    let mut left = a.clone();
+    left.merge(&b);
+    left.merge(&c);
+    let mut right = c.clone();
+    right.merge(&b);
+    right.merge(&a);
+    // merging a state twice changes nothing
+    right.merge(&a);
+
+    assert_eq!(left, right);
+    assert_eq!(left.value(), 9);
+
+    // a stale copy of n0 doesn't undo its later adds
+    a.add("n0", 1);
+    let mut stale = left.clone();
+    stale.merge(&a);
+    assert_eq!(stale.value(), 10);
+}
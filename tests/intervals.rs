@@ -0,0 +1,35 @@
+use distributed_systems::intervals::IntervalSet;
+use serde_json::json;
+
+#[test]
+fn encodes_runs() {
+    let set: IntervalSet = [7, 3, 1, 2, 9, 8, 20].into_iter().collect();
+    assert_eq!(set.runs(), &[(1, 3), (7, 9), (20, 20)]);
+    assert_eq!(set.len(), 7);
+    assert!(set.contains(8) && !set.contains(5));
+    assert_eq!(
+        serde_json::to_value(&set).unwrap(),
+        json!([[1, 3], [7, 9], 20])
+    );
+}
+
+#[test]
+fn decodes_plain_and_overlapping_runs() {
+    let set: IntervalSet = serde_json::from_value(json!([5, [1, 3], [2, 4], 10])).unwrap();
+    assert_eq!(set.runs(), &[(1, 5), (10, 10)]);
+
+    let dense: IntervalSet = (0..10_000).collect();
+    assert_eq!(serde_json::to_string(&dense).unwrap(), "[[0,9999]]");
+}
+
+#[test]
+fn rejects_oversized_sets() {
+    let everything: Result<IntervalSet, _> = serde_json::from_value(json!([[0, u64::MAX]]));
+    assert!(everything.is_err());
+    let split: Result<IntervalSet, _> =
+        serde_json::from_value(json!([[0, 1 << 19], [1 << 19, 1 << 20]]));
+    assert!(split.is_err());
+
+    let most: IntervalSet = serde_json::from_value(json!([[1, 1 << 20]])).unwrap();
+    assert_eq!(most.len(), 1 << 20);
+}
@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use distributed_systems::kafka::store::{Fsync, Store, StoreConfig};
+use distributed_systems::kafka::{KeyLog, PollLimit, Retention};
+use distributed_systems::kv::Consistency;
+use distributed_systems::simulator::{Client, Config, Simulator};
+use serde_json::{json, Value};
+
+async fn start(bin: &str, env: &[(&str, &str)]) -> (Simulator, Client) {
+    let sim = Simulator::start(bin, Config::default().with_env(env))
+        .await
+        .unwrap();
+    let client = sim.client();
+    (sim, client)
+}
+
+// an empty directory of its own for every test
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kafka-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+async fn poll(client: &Client, node: &str, offsets: Value) -> Value {
+    let res = client
+        .rpc(node, json!({"type": "poll", "offsets": offsets}))
+        .await
+        .unwrap();
+    res.body.extra["msgs"].clone()
+}
+
+#[test]
+fn key_log_polls_from_the_next_present_offset() {
+    let mut log = KeyLog::default();
+    for (offset, msg) in [(0, 10), (3, 13), (4, 14), (9, 19)] {
+        log.insert(offset, msg);
+    }
+    assert_eq!(log.append(20), 10);
+
+    let all = PollLimit::default();
+    assert_eq!(log.poll(1, all), &[(3, 13), (4, 14), (9, 19), (10, 20)]);
+    assert_eq!(log.poll(5, all), &[(9, 19), (10, 20)]);
+    assert!(log.poll(11, all).is_empty());
+
+    let two = PollLimit {
+        max_msgs: 2,
+        max_bytes: 0,
+    };
+    assert_eq!(log.poll(0, two), &[(0, 10), (3, 13)]);
+    // "[3,13]" and "[4,14]" are 6 bytes each
+    let bytes = PollLimit {
+        max_msgs: 0,
+        max_bytes: 13,
+    };
+    assert_eq!(log.poll(3, bytes), &[(3, 13), (4, 14)]);
+    // an oversized first entry still goes out
+    let tiny = PollLimit {
+        max_msgs: 0,
+        max_bytes: 1,
+    };
+    assert_eq!(log.poll(3, tiny), &[(3, 13)]);
+}
+
+#[tokio::test]
+async fn single_poll_catches_up_in_pages() {
+    let (sim, client) = start(
+        env!("CARGO_BIN_EXE_kafka_single"),
+        &[("KAFKA_MAX_POLL_MSGS", "4")],
+    )
+    .await;
+    for i in 0..10 {
+        client
+            .rpc("n0", json!({"type": "send", "key": "k1", "msg": 100 + i}))
+            .await
+            .unwrap();
+    }
+    let mut from = 0;
+    let mut seen = Vec::new();
+    loop {
+        let msgs = poll(&client, "n0", json!({"k1": from})).await;
+        let Some(page) = msgs["k1"].as_array() else {
+            break;
+        };
+        assert!(page.len() <= 4);
+        for entry in page {
+            seen.push(entry[1].as_u64().unwrap());
+            from = entry[0].as_u64().unwrap() + 1;
+        }
+    }
+    assert_eq!(seen, (100..110).collect::<Vec<_>>());
+    assert_eq!(poll(&client, "n0", json!({"k2": 0})).await, json!({}));
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn single_commits_merge_and_never_regress() {
+    let (sim, client) = start(env!("CARGO_BIN_EXE_kafka_single"), &[]).await;
+    let commit = |offsets: Value| json!({"type": "commit_offsets", "offsets": offsets});
+    let list = |keys: Value| json!({"type": "list_committed_offsets", "keys": keys});
+
+    client
+        .rpc("n0", commit(json!({"k1": 5, "k2": 3})))
+        .await
+        .unwrap();
+    // k2 is left alone, k1 doesn't move back
+    client.rpc("n0", commit(json!({"k1": 2}))).await.unwrap();
+    client.rpc("n0", commit(json!({"k3": 1}))).await.unwrap();
+
+    let res = client
+        .rpc("n0", list(json!(["k1", "k2", "k4"])))
+        .await
+        .unwrap();
+    assert_eq!(res.body.extra["offsets"], json!({"k1": 5, "k2": 3}));
+    let res = client.rpc("n0", list(json!(["k3"]))).await.unwrap();
+    assert_eq!(res.body.extra["offsets"], json!({"k3": 1}));
+
+    client.rpc("n0", commit(json!({"k1": 7}))).await.unwrap();
+    let res = client.rpc("n0", list(json!(["k1"]))).await.unwrap();
+    assert_eq!(res.body.extra["offsets"], json!({"k1": 7}));
+    sim.shutdown().await;
+}
+
+#[test]
+fn store_recovers_segments_and_cuts_torn_tails() {
+    let dir = scratch("store");
+    let config = StoreConfig {
+        segment_bytes: 40,
+        index_interval: 16,
+        fsync: Fsync::Never,
+    };
+    let (mut store, recovered) = Store::open(&dir, config).unwrap();
+    assert!(recovered.logs.is_empty());
+    for offset in 0..20 {
+        store.append("k1", offset, 100 + offset).unwrap();
+    }
+    store.append("a/b", 0, 7).unwrap();
+    store.append("", 0, 8).unwrap();
+    store
+        .save_commits(&HashMap::from([("k1".to_string(), 5)]))
+        .unwrap();
+    drop(store);
+
+    let segments: Vec<_> = fs::read_dir(dir.join("logs/k1"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "log"))
+        .collect();
+    assert!(segments.len() > 1, "rolled into {segments:?}");
+    // the node died halfway through writing the next message and its index
+    let last = segments.iter().max().unwrap();
+    OpenOptions::new()
+        .append(true)
+        .open(last)
+        .unwrap()
+        .write_all(b"[20,1")
+        .unwrap();
+    let index = last.with_extension("index");
+    OpenOptions::new()
+        .append(true)
+        .open(&index)
+        .unwrap()
+        .write_all(&[1, 2, 3])
+        .unwrap();
+
+    let (mut store, recovered) = Store::open(&dir, config).unwrap();
+    let k1 = &recovered.logs["k1"];
+    assert_eq!(k1.len(), 20);
+    assert_eq!(k1.next_offset(), 20);
+    assert_eq!(recovered.logs["a/b"].entries(), &[(0, 7)]);
+    assert_eq!(recovered.logs[""].entries(), &[(0, 8)]);
+    assert_eq!(recovered.committed, HashMap::from([("k1".to_string(), 5)]));
+    assert_eq!(fs::metadata(&index).unwrap().len() % 16, 0);
+
+    store.append("k1", 20, 120).unwrap();
+    let three = PollLimit {
+        max_msgs: 3,
+        max_bytes: 0,
+    };
+    assert_eq!(
+        store.read("k1", 7, three).unwrap(),
+        vec![(7, 107), (8, 108), (9, 109)]
+    );
+    assert_eq!(
+        store.read("k1", 19, PollLimit::default()).unwrap(),
+        vec![(19, 119), (20, 120)]
+    );
+    // "[10,110]" and "[11,111]" are 8 bytes each
+    let bytes = PollLimit {
+        max_msgs: 0,
+        max_bytes: 20,
+    };
+    assert_eq!(
+        store.read("k1", 10, bytes).unwrap(),
+        vec![(10, 110), (11, 111)]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn single_recovers_its_logs_after_a_restart() {
+    let dir = scratch("single");
+    let (mut sim, client) = start(
+        env!("CARGO_BIN_EXE_kafka_single"),
+        &[
+            ("KAFKA_DATA_DIR", dir.to_str().unwrap()),
+            ("KAFKA_SEGMENT_BYTES", "64"),
+            ("KAFKA_MAX_POLL_MSGS", "2"),
+        ],
+    )
+    .await;
+    for i in 0..10 {
+        let key = if i % 2 == 0 { "k1" } else { "k2" };
+        client
+            .rpc("n0", json!({"type": "send", "key": key, "msg": i}))
+            .await
+            .unwrap();
+    }
+    client
+        .rpc(
+            "n0",
+            json!({"type": "commit_offsets", "offsets": {"k1": 3}}),
+        )
+        .await
+        .unwrap();
+    let before = poll(&client, "n0", json!({"k1": 0, "k2": 0})).await;
+
+    sim.restart("n0").await.unwrap();
+    assert_eq!(poll(&client, "n0", json!({"k1": 0, "k2": 0})).await, before);
+    // served from disk, within the poll limit
+    assert_eq!(
+        poll(&client, "n0", json!({"k2": 1})).await,
+        json!({"k2": [[1, 3], [2, 5]]})
+    );
+    let res = client
+        .rpc(
+            "n0",
+            json!({"type": "list_committed_offsets", "keys": ["k1"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.body.extra["offsets"], json!({"k1": 3}));
+    let res = client
+        .rpc("n0", json!({"type": "send", "key": "k1", "msg": 10}))
+        .await
+        .unwrap();
+    assert_eq!(res.body.extra["offset"], 5);
+    sim.shutdown().await;
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn retention_trims_the_front_and_keeps_offsets() {
+    let mut log = KeyLog::default();
+    for msg in 0..10 {
+        log.append(msg);
+    }
+    let now = Instant::now();
+    let count = Retention {
+        max_msgs: 6,
+        ..Retention::default()
+    };
+    assert_eq!(log.retain(&count, None, now), 4);
+    // "[8,8]" and "[9,9]" are 5 bytes each
+    let bytes = Retention {
+        max_bytes: 12,
+        ..Retention::default()
+    };
+    assert_eq!(log.retain(&bytes, None, now), 8);
+    let compact = Retention {
+        compact: true,
+        ..Retention::default()
+    };
+    assert_eq!(log.retain(&compact, None, now), 8);
+    assert_eq!(log.retain(&compact, Some(9), now), 9);
+    assert_eq!(log.poll(0, PollLimit::default()), &[(9, 9)]);
+
+    let age = Retention {
+        max_age: Some(Duration::from_secs(60)),
+        ..Retention::default()
+    };
+    assert_eq!(log.retain(&age, None, now), 9);
+    assert_eq!(log.retain(&age, None, now + Duration::from_secs(61)), 10);
+    assert!(log.is_empty());
+    assert_eq!(log.append(10), 10);
+}
+
+#[tokio::test]
+async fn single_retention_drops_old_segments() {
+    let dir = scratch("retention");
+    let (sim, client) = start(
+        env!("CARGO_BIN_EXE_kafka_single"),
+        &[
+            ("KAFKA_DATA_DIR", dir.to_str().unwrap()),
+            ("KAFKA_SEGMENT_BYTES", "16"),
+            ("KAFKA_RETENTION_MSGS", "3"),
+            ("KAFKA_COMPACT_COMMITTED", "true"),
+            ("KAFKA_RETENTION_CHECK_MS", "20"),
+        ],
+    )
+    .await;
+    for i in 0..10 {
+        client
+            .rpc("n0", json!({"type": "send", "key": "k1", "msg": i}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        poll(&client, "n0", json!({"k1": 0})).await,
+        json!({"k1": [[7, 7], [8, 8], [9, 9]]})
+    );
+    // three 6-byte lines per segment: 6..=8 and 9.. are left, log and index
+    let files = fs::read_dir(dir.join("n0/logs/k1")).unwrap().count();
+    assert_eq!(files, 4);
+
+    client
+        .rpc(
+            "n0",
+            json!({"type": "commit_offsets", "offsets": {"k1": 9}}),
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        poll(&client, "n0", json!({"k1": 0})).await,
+        json!({"k1": [[9, 9]]})
+    );
+    let res = client
+        .rpc("n0", json!({"type": "send", "key": "k1", "msg": 10}))
+        .await
+        .unwrap();
+    assert_eq!(res.body.extra["offset"], 10);
+    sim.shutdown().await;
+    fs::remove_dir_all(dir).unwrap();
+}
+
+async fn start_distributed(env: &[(&str, &str)]) -> (Simulator, Client) {
+    let config = Config {
+        node_count: 3,
+        services: vec![("lin-kv".to_string(), Consistency::Linearizable)],
+        ..Config::default()
+    }
+    .with_env(env);
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_kafka_distributed"), config)
+        .await
+        .unwrap();
+    let client = sim.client();
+    (sim, client)
+}
+
+#[tokio::test]
+async fn distributed_sends_go_to_the_key_owner() {
+    let (sim, client) = start_distributed(&[]).await;
+    let keys = ["k1", "k2", "k3", "k4", "k5"];
+    for i in 0..20u64 {
+        let node = &sim.node_ids()[i as usize % 3];
+        let key = keys[i as usize % keys.len()];
+        let res = client
+            .rpc(node, json!({"type": "send", "key": key, "msg": i}))
+            .await
+            .unwrap();
+        assert_eq!(res.body.extra["offset"], i / keys.len() as u64);
+    }
+    let offsets: Value = keys.iter().map(|k| (k.to_string(), json!(0))).collect();
+    let expected = poll(&client, "n0", offsets.clone()).await;
+    assert_eq!(expected["k2"], json!([[0, 1], [1, 6], [2, 11], [3, 16]]));
+    for node in ["n1", "n2"] {
+        assert_eq!(poll(&client, node, offsets.clone()).await, expected);
+    }
+    // a read and a cas per key, reserving its first block of offsets
+    assert_eq!(sim.stats().service_msgs, 2 * keys.len() as u64);
+
+    client
+        .rpc(
+            "n1",
+            json!({"type": "commit_offsets", "offsets": {"k1": 2, "k2": 1}}),
+        )
+        .await
+        .unwrap();
+    client
+        .rpc(
+            "n2",
+            json!({"type": "commit_offsets", "offsets": {"k1": 1}}),
+        )
+        .await
+        .unwrap();
+    let res = client
+        .rpc(
+            "n0",
+            json!({"type": "list_committed_offsets", "keys": ["k1", "k2", "k3"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.body.extra["offsets"], json!({"k1": 2, "k2": 1}));
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn distributed_owners_never_reuse_offsets_after_a_restart() {
+    let (mut sim, client) = start_distributed(&[("KAFKA_OFFSET_BLOCK", "2")]).await;
+    for i in 0..3 {
+        let res = client
+            .rpc("n0", json!({"type": "send", "key": "k1", "msg": i}))
+            .await
+            .unwrap();
+        assert_eq!(res.body.extra["offset"], i);
+    }
+
+    // whichever node owns k1 comes back with an empty log
+    for node in sim.node_ids().to_vec() {
+        sim.restart(&node).await.unwrap();
+    }
+    let res = client
+        .rpc("n1", json!({"type": "send", "key": "k1", "msg": 3}))
+        .await
+        .unwrap();
+    // offset 3 was reserved along with 2, so the next block starts at 4
+    assert_eq!(res.body.extra["offset"], 4);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn distributed_retention_trims_at_the_owner() {
+    let (sim, client) = start_distributed(&[
+        ("KAFKA_RETENTION_MSGS", "3"),
+        ("KAFKA_COMPACT_COMMITTED", "true"),
+        ("KAFKA_RETENTION_CHECK_MS", "20"),
+    ])
+    .await;
+    for i in 0..6 {
+        let node = &sim.node_ids()[i % 3];
+        client
+            .rpc(node, json!({"type": "send", "key": "k1", "msg": i}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        poll(&client, "n1", json!({"k1": 1})).await,
+        json!({"k1": [[3, 3], [4, 4], [5, 5]]})
+    );
+    client
+        .rpc(
+            "n0",
+            json!({"type": "commit_offsets", "offsets": {"k1": 5}}),
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        poll(&client, "n2", json!({"k1": 0})).await,
+        json!({"k1": [[5, 5]]})
+    );
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn distributed_concurrent_commits_never_go_back() {
+    // wide latencies, so the owners' writes to lin-kv overtake each other
+    let config = Config {
+        node_count: 3,
+        max_latency: Duration::from_millis(20),
+        services: vec![("lin-kv".to_string(), Consistency::Linearizable)],
+        ..Config::default()
+    };
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_kafka_distributed"), config)
+        .await
+        .unwrap();
+    let client = sim.client();
+    let keys: Vec<String> = (0..20).map(|k| format!("k{k}")).collect();
+    for round in 0..5u64 {
+        let mut commits = Vec::new();
+        for i in 1..=10 {
+            let offsets: Value = keys
+                .iter()
+                .map(|k| (k.clone(), json!(round * 10 + i)))
+                .collect();
+            let (client, node) = (sim.client(), sim.node_ids()[i as usize % 3].clone());
+            commits.push(tokio::spawn(async move {
+                client
+                    .rpc(&node, json!({"type": "commit_offsets", "offsets": offsets}))
+                    .await
+            }));
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        for commit in commits {
+            commit.await.unwrap().unwrap();
+        }
+        let expected: Value = keys
+            .iter()
+            .map(|k| (k.clone(), json!(round * 10 + 10)))
+            .collect();
+        // every node lists from lin-kv, any one will do
+        let node = &sim.node_ids()[round as usize % 3];
+        let res = client
+            .rpc(
+                node,
+                json!({"type": "list_committed_offsets", "keys": keys}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.body.extra["offsets"], expected, "round {round}");
+    }
+    sim.shutdown().await;
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use distributed_systems::kv::Consistency;
+use distributed_systems::simulator::{Config, Simulator};
+use serde_json::json;
+
+#[tokio::test]
+async fn counter_over_seq_kv() {
+    let config = Config {
+        node_count: 3,
+        services: vec![(
+            "seq-kv".to_string(),
+            Consistency::Sequential { staleness: 0 },
+        )],
+        ..Config::default()
+    };
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_counter"), config)
+        .await
+        .unwrap();
+    let client = sim.client();
+    for (i, node) in sim.node_ids().iter().enumerate() {
+        client
+            .rpc(node, json!({"type": "add", "delta": i + 1}))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for node in sim.node_ids() {
+        let res = client.rpc(node, json!({"type": "read"})).await.unwrap();
+        assert_eq!(res.body.extra["value"], 6, "{node}");
+    }
+    assert!(sim.stats().service_msgs > 0);
+    sim.shutdown().await;
+}
+
+#[tokio::test]
+async fn kafka_over_lin_kv() {
+    let config = Config {
+        node_count: 2,
+        services: vec![("lin-kv".to_string(), Consistency::Linearizable)],
+        ..Config::default()
+    };
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_kafka_distributed"), config)
+        .await
+        .unwrap();
+    let client = sim.client();
+    let mut offsets = Vec::new();
+    for (i, node) in ["n0", "n1", "n0"].iter().enumerate() {
+        let res = client
+            .rpc(node, json!({"type": "send", "key": "k1", "msg": i * 10}))
+            .await
+            .unwrap();
+        offsets.push(res.body.extra["offset"].as_u64().unwrap());
+    }
+    assert_eq!(offsets, vec![0, 1, 2]);
+
+    let res = client
+        .rpc("n1", json!({"type": "poll", "offsets": {"k1": 1}}))
+        .await
+        .unwrap();
+    assert_eq!(res.body.extra["msgs"], json!({"k1": [[1, 10], [2, 20]]}));
+    // sends and polls stay with the key's owner, but for reserving a block
+    // of offsets with a read and a cas; commits go through lin-kv
+    assert_eq!(sim.stats().service_msgs, 2);
+
+    client
+        .rpc(
+            "n0",
+            json!({"type": "commit_offsets", "offsets": {"k1": 2}}),
+        )
+        .await
+        .unwrap();
+    let res = client
+        .rpc(
+            "n1",
+            json!({"type": "list_committed_offsets", "keys": ["k1"]}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.body.extra["offsets"], json!({"k1": 2}));
+    assert!(sim.stats().service_msgs > 2);
+    sim.shutdown().await;
+}
@@ -0,0 +1,17 @@
+use distributed_systems::simulator::{Config, Simulator};
+use serde_json::json;
+
+#[tokio::test]
+async fn echo_roundtrip() {
+    let sim = Simulator::start(env!("CARGO_BIN_EXE_echo"), Config::default())
+        .await
+        .unwrap();
+    let client = sim.client();
+    let res = client
+        .rpc("n0", json!({"type": "echo", "echo": "hello"}))
+        .await
+        .unwrap();
+    assert_eq!(res.get_type(), "echo_ok");
+    assert_eq!(res.body.extra["echo"], "hello");
+    sim.shutdown().await;
+}